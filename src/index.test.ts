@@ -2,16 +2,50 @@ import { before, describe, test } from "node:test";
 import {
   getKeypairFromEnvironment,
   getKeypairFromFile,
+  parseSecretKey,
+  redactKeypair,
+  RedactedKeypair,
+  getSolanaCliConfig,
+  getConnectionAndKeypairFromCliConfig,
+  SecretKeyParseError,
+  KeypairValidationError,
+  getKeypairFromMnemonic,
+  saveEncryptedKeypair,
+  decryptKeypair,
+  saveKeypairToFile,
   addKeypairToEnvFile,
+  addEnvFileVariable,
+  getEnvFileVariable,
+  replaceEnvFileVariable,
+  removeEnvFileVariable,
+  rotateKeypair,
   getCustomErrorMessage,
   airdropIfRequired,
+  AirdropError,
+  TransactionFailedError,
+  fundIfRequired,
+  getClusterFromConnection,
+  airdropIfRequiredMany,
+  mintTokensIfRequired,
   getExplorerLink,
+  getExplorerLinkForConnection,
+  parseExplorerLink,
   confirmTransaction,
   makeKeypairs,
+  grindKeypair,
+  getExpectedGrindAttempts,
   InitializeKeypairOptions,
   initializeKeypair,
+  initializeKeypairs,
+  KeypairStorage,
+  saveKeypairToBrowserStorage,
+  getKeypairFromBrowserStorage,
   getLogs,
   getSimulationComputeUnits,
+  makeKeypairSigner,
+  makeCommandSigner,
+  makeHttpSigner,
+  makeAndSendAndConfirmTransaction,
 } from "./index";
 import {
   Connection,
@@ -21,13 +55,32 @@ import {
   SystemProgram,
   Transaction,
   TransactionInstruction,
+  TransactionMessage,
+  VersionedTransaction,
 } from "@solana/web3.js";
 import assert from "node:assert/strict";
 import base58 from "bs58";
+import { ed25519 } from "@noble/curves/ed25519";
+import { createServer } from "node:http";
+import { AddressInfo } from "node:net";
 // See https://m.media-amazon.com/images/I/51TJeGHxyTL._SY445_SX342_.jpg
 import { exec as execNoPromises } from "child_process";
-import { promisify } from "util";
-import { writeFile, unlink as deleteFile } from "node:fs/promises";
+import { inspect, promisify } from "util";
+import {
+  writeFile,
+  readFile,
+  rm,
+  stat,
+  chmod,
+  unlink as deleteFile,
+} from "node:fs/promises";
+import {
+  createAssociatedTokenAccount,
+  createMint,
+  freezeAccount,
+  mintTo,
+  TOKEN_PROGRAM_ID,
+} from "@solana/spl-token";
 import dotenv from "dotenv";
 import { createAccountsMintsAndTokenAccounts } from "./index.js";
 
@@ -101,6 +154,237 @@ describe("getKeypairFromFile", () => {
       message: `Invalid secret key file at '${CORRUPT_TEST_FILE_NAME}'!`,
     });
   });
+
+  test("throws a nice error if the public key in the file doesn't match the seed", async () => {
+    const MISMATCHED_TEST_FILE_NAME = `${TEMP_DIR}/mismatched-keyfile-do-not-use.json`;
+    const secretKey = Uint8Array.from([
+      ...Keypair.generate().secretKey.slice(0, 32),
+      ...Keypair.generate().publicKey.toBytes(),
+    ]);
+    await writeFile(
+      MISMATCHED_TEST_FILE_NAME,
+      JSON.stringify(Array.from(secretKey)),
+    );
+    await chmod(MISMATCHED_TEST_FILE_NAME, 0o600);
+    await assert.rejects(
+      () => getKeypairFromFile(MISMATCHED_TEST_FILE_NAME),
+      {
+        message: `Invalid secret key file at '${MISMATCHED_TEST_FILE_NAME}'!`,
+      },
+    );
+  });
+
+  test("strict mode rejects files other users can read", async () => {
+    const READABLE_TEST_FILE_NAME = `${TEMP_DIR}/readable-keyfile-do-not-use.json`;
+    await writeFile(
+      READABLE_TEST_FILE_NAME,
+      JSON.stringify(Array.from(Keypair.generate().secretKey)),
+    );
+    await chmod(READABLE_TEST_FILE_NAME, 0o644);
+
+    // Only a warning by default
+    await getKeypairFromFile(READABLE_TEST_FILE_NAME);
+
+    await assert.rejects(
+      () => getKeypairFromFile(READABLE_TEST_FILE_NAME, { strict: true }),
+      (error) => {
+        assert.ok(error instanceof KeypairValidationError);
+        assert.equal(error.problem, "file-permissions");
+        return true;
+      },
+    );
+  });
+
+  test("strict mode rejects files that aren't in the solana-keygen format", async () => {
+    const BASE58_TEST_FILE_NAME = `${TEMP_DIR}/base58-keyfile-do-not-use.txt`;
+    await writeFile(
+      BASE58_TEST_FILE_NAME,
+      base58.encode(Keypair.generate().secretKey),
+    );
+    await chmod(BASE58_TEST_FILE_NAME, 0o600);
+
+    await getKeypairFromFile(BASE58_TEST_FILE_NAME);
+
+    await assert.rejects(
+      () => getKeypairFromFile(BASE58_TEST_FILE_NAME, { strict: true }),
+      (error) => {
+        assert.ok(error instanceof KeypairValidationError);
+        assert.equal(error.problem, "layout");
+        return true;
+      },
+    );
+  });
+
+  test("checks the keypair is for the expected public key", async () => {
+    const keypair = await getKeypairFromFile(TEST_FILE_NAME);
+    await getKeypairFromFile(TEST_FILE_NAME, {
+      strict: true,
+      expectedPublicKey: keypair.publicKey,
+    });
+
+    const otherAddress = Keypair.generate().publicKey.toBase58();
+    await assert.rejects(
+      () =>
+        getKeypairFromFile(TEST_FILE_NAME, { expectedPublicKey: otherAddress }),
+      {
+        message: `Keypair file at '${TEST_FILE_NAME}' is for '${keypair.publicKey.toBase58()}', expected '${otherAddress}'.`,
+      },
+    );
+  });
+});
+
+describe("saveKeypairToFile", () => {
+  const SAVED_KEYPAIRS_DIR = `${TEMP_DIR}/saved-keypairs`;
+  // The parent directory doesn't exist yet, so will be created
+  const TEST_FILE_NAME = `${SAVED_KEYPAIRS_DIR}/nested/test-keyfile-do-not-use.json`;
+
+  before(async () => {
+    await rm(SAVED_KEYPAIRS_DIR, { recursive: true, force: true });
+  });
+
+  test("saves a keypair in the solana-keygen format that only the user can read", async () => {
+    const keypair = Keypair.generate();
+    await saveKeypairToFile(keypair, TEST_FILE_NAME);
+
+    const fileContents = await readFile(TEST_FILE_NAME, "utf8");
+    assert.equal(fileContents, JSON.stringify(Array.from(keypair.secretKey)));
+    const loadedKeypair = await getKeypairFromFile(TEST_FILE_NAME);
+    assert.ok(loadedKeypair.publicKey.equals(keypair.publicKey));
+
+    const { mode } = await stat(TEST_FILE_NAME);
+    assert.equal(mode & 0o777, 0o600);
+  });
+
+  test("throws a nice error rather than overwriting an existing file", async () => {
+    await assert.rejects(
+      () => saveKeypairToFile(Keypair.generate(), TEST_FILE_NAME),
+      {
+        message: `File at '${TEST_FILE_NAME}' already exists, set 'overwrite' to replace it.`,
+      },
+    );
+  });
+
+  test("overwrites an existing file when asked to", async () => {
+    const keypair = Keypair.generate();
+    await saveKeypairToFile(keypair, TEST_FILE_NAME, { overwrite: true });
+    const loadedKeypair = await getKeypairFromFile(TEST_FILE_NAME);
+    assert.ok(loadedKeypair.publicKey.equals(keypair.publicKey));
+  });
+});
+
+describe("saveEncryptedKeypair", () => {
+  let ENCRYPTED_TEST_FILE_NAME = `${TEMP_DIR}/encrypted-keyfile-do-not-use.json`;
+  const PASSWORD = "correct horse battery staple";
+  let testKeypair: Keypair;
+
+  before(async () => {
+    testKeypair = Keypair.generate();
+    await saveEncryptedKeypair(
+      testKeypair,
+      ENCRYPTED_TEST_FILE_NAME,
+      PASSWORD,
+      { overwrite: true },
+    );
+  });
+
+  test("getKeypairFromFile loads an encrypted keypair with the password", async () => {
+    const keypair = await getKeypairFromFile(ENCRYPTED_TEST_FILE_NAME, {
+      password: PASSWORD,
+    });
+    assert.ok(keypair.publicKey.equals(testKeypair.publicKey));
+  });
+
+  test("throws a nice error if no password is given for an encrypted file", async () => {
+    await assert.rejects(() => getKeypairFromFile(ENCRYPTED_TEST_FILE_NAME), {
+      message: `Keypair file at '${ENCRYPTED_TEST_FILE_NAME}' is encrypted, please provide a password.`,
+    });
+  });
+
+  test("throws a nice error if the password is wrong", async () => {
+    await assert.rejects(
+      () =>
+        getKeypairFromFile(ENCRYPTED_TEST_FILE_NAME, {
+          password: "wrong password",
+        }),
+      {
+        message: `Could not decrypt keypair for '${testKeypair.publicKey.toBase58()}', is the password correct?`,
+      },
+    );
+  });
+
+  test("refuses scrypt parameters that would use too much memory", async () => {
+    const encryptedKeypairFile = JSON.parse(
+      await readFile(ENCRYPTED_TEST_FILE_NAME, "utf8"),
+    );
+    encryptedKeypairFile.kdfParams.n = 2 ** 30;
+    await assert.rejects(() => decryptKeypair(encryptedKeypairFile, PASSWORD), {
+      message:
+        "Unsupported scrypt parameters (n 1073741824, r 8, p 1), expected at most n 131072, r 8, p 1",
+    });
+  });
+});
+
+describe("getSolanaCliConfig", () => {
+  const CONFIG_FILE_NAME = `${TEMP_DIR}/test-cli-config.yml`;
+  const KEYPAIR_FILE_NAME = `${TEMP_DIR}/test-cli-config-keyfile-do-not-use.json`;
+  let keypair: Keypair;
+
+  before(async () => {
+    keypair = Keypair.generate();
+    await writeFile(
+      KEYPAIR_FILE_NAME,
+      JSON.stringify(Array.from(keypair.secretKey)),
+    );
+    // The same format that 'solana config set' writes
+    await writeFile(
+      CONFIG_FILE_NAME,
+      [
+        "---",
+        `json_rpc_url: "${LOCALHOST}"`,
+        'websocket_url: ""',
+        `keypair_path: ${KEYPAIR_FILE_NAME}`,
+        "address_labels:",
+        '  "11111111111111111111111111111111": System Program',
+        "commitment: processed",
+        "",
+      ].join("\n"),
+    );
+  });
+
+  test("getSolanaCliConfig reads the settings from a Solana CLI config file", async () => {
+    const config = await getSolanaCliConfig(CONFIG_FILE_NAME);
+    assert.deepEqual(config, {
+      jsonRpcUrl: LOCALHOST,
+      // Like the Solana CLI, the websocket URL uses the next port
+      websocketUrl: "ws://127.0.0.1:8900/",
+      keypairPath: KEYPAIR_FILE_NAME,
+      commitment: "processed",
+    });
+  });
+
+  test("getKeypairFromFile() with no file name uses the keypair from the SOLANA_CONFIG file", async () => {
+    process.env.SOLANA_CONFIG = CONFIG_FILE_NAME;
+    try {
+      const loadedKeypair = await getKeypairFromFile();
+      assert.ok(loadedKeypair.publicKey.equals(keypair.publicKey));
+    } finally {
+      delete process.env.SOLANA_CONFIG;
+    }
+  });
+
+  test("getConnectionAndKeypairFromCliConfig returns a connection and keypair", async () => {
+    const { connection, keypair: loadedKeypair } =
+      await getConnectionAndKeypairFromCliConfig(CONFIG_FILE_NAME);
+    assert.equal(connection.rpcEndpoint, LOCALHOST);
+    assert.equal(connection.commitment, "processed");
+    assert.ok(loadedKeypair.publicKey.equals(keypair.publicKey));
+  });
+
+  test("throws a nice error if a config file is specified but missing", async () => {
+    await assert.rejects(() => getSolanaCliConfig("THIS FILE DOES NOT EXIST"), {
+      message: `Could not read Solana CLI config from 'THIS FILE DOES NOT EXIST'`,
+    });
+  });
 });
 
 describe("getKeypairFromEnvironment", () => {
@@ -145,6 +429,170 @@ describe("getKeypairFromEnvironment", () => {
   });
 });
 
+describe("parseSecretKey", () => {
+  const keypair = Keypair.generate();
+  const secretKey = Buffer.from(keypair.secretKey);
+
+  test("parseSecretKey reads every common secret key format", () => {
+    const inputs = [
+      JSON.stringify(Array.from(secretKey)),
+      base58.encode(secretKey),
+      secretKey.toString("hex"),
+      `0x${secretKey.toString("hex")}`,
+      secretKey.toString("base64"),
+      Array.from(secretKey),
+      keypair.secretKey,
+    ];
+    inputs.forEach((input) => {
+      assert.ok(parseSecretKey(input).publicKey.equals(keypair.publicKey));
+    });
+  });
+
+  test("parseSecretKey reads a 32 byte seed", () => {
+    const seed = secretKey.subarray(0, 32);
+    assert.ok(
+      parseSecretKey(seed.toString("hex")).publicKey.equals(keypair.publicKey),
+    );
+    assert.ok(
+      parseSecretKey(base58.encode(seed)).publicKey.equals(keypair.publicKey),
+    );
+  });
+
+  test("throws a typed error with the format and length if the secret key is the wrong length", () => {
+    assert.throws(
+      () => parseSecretKey(secretKey.subarray(0, 20).toString("hex")),
+      (error) => {
+        assert.ok(error instanceof SecretKeyParseError);
+        assert.equal(error.format, "hex");
+        assert.equal(error.length, 20);
+        assert.deepEqual(error.expectedLengths, [32, 64]);
+        assert.equal(
+          error.message,
+          "Secret key looks like hex but is 20 bytes long, expected 32 or 64 bytes",
+        );
+        return true;
+      },
+    );
+  });
+
+  test("throws a typed error if the public key doesn't match the seed", () => {
+    const otherKeypair = Keypair.generate();
+    const mismatchedSecretKey = [
+      ...secretKey.subarray(0, 32),
+      ...otherKeypair.publicKey.toBytes(),
+    ];
+    assert.throws(() => parseSecretKey(mismatchedSecretKey), {
+      name: "SecretKeyParseError",
+      message:
+        "Secret key (bytes) is 64 bytes long, but the public key doesn't match the seed",
+    });
+  });
+});
+
+describe("RedactedKeypair", () => {
+  test("redacts the secret key when logged or serialized", () => {
+    const keypair = Keypair.generate();
+    const redactedKeypair = redactKeypair(keypair);
+    const address = keypair.publicKey.toBase58();
+    const secretKeyJSON = JSON.stringify(Array.from(keypair.secretKey));
+
+    assert.equal(String(redactedKeypair), `RedactedKeypair(${address})`);
+    assert.equal(inspect(redactedKeypair), `RedactedKeypair(${address})`);
+    assert.equal(
+      JSON.stringify({ redactedKeypair }),
+      `{"redactedKeypair":{"publicKey":"${address}"}}`,
+    );
+    assert.ok(!JSON.stringify({ ...redactedKeypair }).includes(secretKeyJSON));
+
+    // But it's still a working keypair
+    assert.ok(redactedKeypair instanceof Keypair);
+    assert.ok(redactedKeypair.publicKey.equals(keypair.publicKey));
+    assert.deepEqual(redactedKeypair.secretKey, keypair.secretKey);
+  });
+
+  test("dispose() zeroes the secret key", () => {
+    const redactedKeypair = redactKeypair(Keypair.generate());
+    redactedKeypair.dispose();
+    assert.throws(() => redactedKeypair.secretKey, {
+      message: `The keypair for '${redactedKeypair.publicKey.toBase58()}' has been disposed`,
+    });
+  });
+
+  test("keypair loaders return a RedactedKeypair when asked to", () => {
+    const keypair = Keypair.generate();
+    process.env.TEST_ENV_VAR_REDACTED = base58.encode(keypair.secretKey);
+    const loadedKeypair = getKeypairFromEnvironment("TEST_ENV_VAR_REDACTED", {
+      redact: true,
+    });
+    assert.ok(loadedKeypair instanceof RedactedKeypair);
+    assert.ok(loadedKeypair.publicKey.equals(keypair.publicKey));
+  });
+});
+
+describe("getKeypairFromMnemonic", () => {
+  let TEST_FILE_NAME = `${TEMP_DIR}/test-mnemonic-keyfile-do-not-use.json`;
+  let mnemonic: string;
+
+  before(async () => {
+    const { stdout } = await exec(
+      `solana-keygen new --force --no-bip39-passphrase -o ${TEST_FILE_NAME}`,
+    );
+    // The seed phrase is printed on the line after this message
+    const lines = stdout.split("\n");
+    const index = lines.findIndex((line) =>
+      line.includes("Save this seed phrase"),
+    );
+    mnemonic = lines[index + 1].trim();
+  });
+
+  test("getting the same keypair as the Solana CLI from a mnemonic", async () => {
+    const keypairFromFile = await getKeypairFromFile(TEST_FILE_NAME);
+    const keypairFromMnemonic = getKeypairFromMnemonic(mnemonic);
+    assert.ok(keypairFromMnemonic.publicKey.equals(keypairFromFile.publicKey));
+  });
+
+  test("getting different keypairs for different wallet derivation paths", () => {
+    const firstKeypair = getKeypairFromMnemonic(mnemonic, {
+      derivationPath: "m/44'/501'/0'/0'",
+    });
+    const secondKeypair = getKeypairFromMnemonic(mnemonic, {
+      derivationPath: "m/44'/501'/1'/0'",
+    });
+    assert.ok(!firstKeypair.publicKey.equals(secondKeypair.publicKey));
+  });
+
+  test("throws a nice error if the mnemonic has the wrong number of words", () => {
+    assert.throws(() => getKeypairFromMnemonic("not enough words"), {
+      message: `Invalid mnemonic, expected 12, 15, 18, 21, 24 words but got 3`,
+    });
+  });
+
+  test("throws a nice error if a word isn't in the wordlist", () => {
+    const mnemonicWithTypo = `${"abandon ".repeat(11)}abandonn`;
+    assert.throws(() => getKeypairFromMnemonic(mnemonicWithTypo), {
+      message: `Invalid mnemonic, 'abandonn' isn't in the BIP39 English wordlist`,
+    });
+  });
+
+  test("throws a nice error if the mnemonic checksum doesn't match", () => {
+    // 'abandon' x 11 then 'about' is valid, so the last word is wrong here
+    const mnemonicWithBadChecksum = "abandon ".repeat(12).trim();
+    assert.throws(() => getKeypairFromMnemonic(mnemonicWithBadChecksum), {
+      message: `Invalid mnemonic, the checksum doesn't match, check the words are correct and in the right order`,
+    });
+  });
+
+  test("throws a nice error if the derivation path isn't hardened", () => {
+    assert.throws(
+      () =>
+        getKeypairFromMnemonic(mnemonic, { derivationPath: "m/44'/501'/0" }),
+      {
+        message: `Invalid derivation path 'm/44'/501'/0', every segment must be hardened (like 501')`,
+      },
+    );
+  });
+});
+
 describe("addKeypairToEnvFile", () => {
   let TEST_ENV_VAR_ARRAY_OF_NUMBERS = "TEST_ENV_VAR_ARRAY_OF_NUMBERS";
   let testKeypair: Keypair;
@@ -179,14 +627,105 @@ describe("addKeypairToEnvFile", () => {
     await deleteFile(envFileName);
   });
 
-  test("throws a nice error if the env var already exists", async () => {
-    assert.rejects(
+  test("throws a nice error if the env var already exists in the env file", async () => {
+    const envFileName = ".env-unittest-addkeypairtoenvfile-existing";
+    await writeFile(
+      envFileName,
+      `${TEST_ENV_VAR_ARRAY_OF_NUMBERS}=${process.env[TEST_ENV_VAR_ARRAY_OF_NUMBERS]}\n`,
+    );
+    await assert.rejects(
       async () =>
-        addKeypairToEnvFile(testKeypair, TEST_ENV_VAR_ARRAY_OF_NUMBERS),
+        addKeypairToEnvFile(
+          testKeypair,
+          TEST_ENV_VAR_ARRAY_OF_NUMBERS,
+          envFileName,
+        ),
       {
         message: `'TEST_ENV_VAR_ARRAY_OF_NUMBERS' already exists in env file.`,
       },
     );
+    await deleteFile(envFileName);
+  });
+
+  test("adds the env var if it's only in the environment, not the env file", async () => {
+    const envFileName = ".env-unittest-addkeypairtoenvfile-environment";
+    await addKeypairToEnvFile(
+      testKeypair,
+      TEST_ENV_VAR_ARRAY_OF_NUMBERS,
+      envFileName,
+    );
+    const secretKeyString = await getEnvFileVariable(
+      envFileName,
+      TEST_ENV_VAR_ARRAY_OF_NUMBERS,
+    );
+    assert.equal(
+      secretKeyString,
+      JSON.stringify(Array.from(testKeypair.secretKey)),
+    );
+    await deleteFile(envFileName);
+  });
+});
+
+describe("env file editing", () => {
+  const envFileName = ".env.unittest.local";
+  const ORIGINAL_CONTENTS = [
+    "# This comment should be kept",
+    "export FIRST=1 # So should this one",
+    'MULTILINE="first line',
+    'second line"',
+    "QUOTED='single quoted'",
+    "",
+    "LAST=last",
+  ].join("\n");
+
+  before(async () => {
+    await writeFile(envFileName, ORIGINAL_CONTENTS);
+  });
+
+  test("getEnvFileVariable reads values, including quoted values", async () => {
+    assert.equal(await getEnvFileVariable(envFileName, "FIRST"), "1");
+    assert.equal(
+      await getEnvFileVariable(envFileName, "MULTILINE"),
+      "first line\nsecond line",
+    );
+    assert.equal(
+      await getEnvFileVariable(envFileName, "QUOTED"),
+      "single quoted",
+    );
+    assert.equal(await getEnvFileVariable(envFileName, "MISSING"), null);
+  });
+
+  test("replaceEnvFileVariable only changes the value", async () => {
+    await replaceEnvFileVariable(envFileName, "FIRST", "changed value");
+    const contents = await readFile(envFileName, "utf8");
+    assert.equal(
+      contents,
+      ORIGINAL_CONTENTS.replace("FIRST=1 # So", "FIRST='changed value' # So"),
+    );
+    assert.equal(
+      await getEnvFileVariable(envFileName, "FIRST"),
+      "changed value",
+    );
+  });
+
+  test("removeEnvFileVariable only removes the variable's line", async () => {
+    assert.ok(await removeEnvFileVariable(envFileName, "QUOTED"));
+    const contents = await readFile(envFileName, "utf8");
+    assert.ok(!contents.includes("QUOTED"));
+    assert.ok(contents.startsWith("# This comment should be kept\n"));
+    assert.ok(contents.endsWith('second line"\n\nLAST=last'));
+    assert.equal(await removeEnvFileVariable(envFileName, "QUOTED"), false);
+  });
+
+  test("addEnvFileVariable adds new variables, but not existing ones", async () => {
+    await addEnvFileVariable(envFileName, "ADDED", "added", "A comment");
+    const contents = await readFile(envFileName, "utf8");
+    assert.ok(contents.endsWith("LAST=last\n# A comment\nADDED=added\n"));
+    await assert.rejects(
+      () => addEnvFileVariable(envFileName, "LAST", "again"),
+      { message: `'LAST' already exists in env file.` },
+    );
+    await deleteFile(envFileName);
   });
 });
 
@@ -236,6 +775,173 @@ describe("initializeKeypair", () => {
   });
 });
 
+describe("browser keypair storage", () => {
+  // A stand-in for IndexedDB or localStorage, which node.js doesn't have
+  const makeMemoryStorage = (): KeypairStorage => {
+    const items = new Map<string, string>();
+    return {
+      getItem: async (key) => items.get(key) ?? null,
+      setItem: async (key, value) => {
+        items.set(key, value);
+      },
+      removeItem: async (key) => {
+        items.delete(key);
+      },
+    };
+  };
+  const PASSWORD = "correct horse battery staple";
+
+  test("saves an encrypted keypair to browser storage and loads it again", async () => {
+    const storage = makeMemoryStorage();
+    const keypair = Keypair.generate();
+    await saveKeypairToBrowserStorage(keypair, "wallet", PASSWORD, {
+      storage,
+    });
+
+    const storedValue = await storage.getItem("solana-keypair:wallet");
+    assert.ok(storedValue);
+    assert.ok(!storedValue.includes(base58.encode(keypair.secretKey)));
+
+    const loadedKeypair = await getKeypairFromBrowserStorage(
+      "wallet",
+      PASSWORD,
+      { storage },
+    );
+    assert.ok(loadedKeypair.publicKey.equals(keypair.publicKey));
+
+    await assert.rejects(
+      () =>
+        getKeypairFromBrowserStorage("wallet", "wrong password", { storage }),
+      {
+        message: `Could not decrypt keypair for '${keypair.publicKey.toBase58()}', is the password correct?`,
+      },
+    );
+  });
+
+  test("refuses PBKDF2 iterations that would take too long", async () => {
+    const storage = makeMemoryStorage();
+    await saveKeypairToBrowserStorage(Keypair.generate(), "wallet", PASSWORD, {
+      storage,
+    });
+    const storedValue = await storage.getItem("solana-keypair:wallet");
+    const encryptedKeypair = JSON.parse(storedValue as string);
+    encryptedKeypair.kdfParams.iterations = 1_000_000_000;
+    await storage.setItem(
+      "solana-keypair:wallet",
+      JSON.stringify(encryptedKeypair),
+    );
+
+    await assert.rejects(
+      () => getKeypairFromBrowserStorage("wallet", PASSWORD, { storage }),
+      {
+        message:
+          "Unsupported PBKDF2 iterations (1000000000), expected at most 600000",
+      },
+    );
+  });
+
+  test("initializeKeypair can use browser storage instead of an env file", async () => {
+    const connection = new Connection(LOCALHOST);
+    const options: InitializeKeypairOptions = {
+      browserStorage: { password: PASSWORD, storage: makeMemoryStorage() },
+      airdropAmount: null,
+    };
+    const firstLoad = await initializeKeypair(connection, options);
+    const secondLoad = await initializeKeypair(connection, options);
+    assert.ok(firstLoad.publicKey.equals(secondLoad.publicKey));
+  });
+});
+
+describe("initializeKeypairs", () => {
+  const connection = new Connection(LOCALHOST);
+
+  test("creates, saves and airdrops to named keypairs, then loads them again", async () => {
+    const envFileName = ".env-unittest-initkeypairs";
+    const names = ["admin", "alice"] as const;
+    const firstLoad = await initializeKeypairs(connection, names, {
+      envFileName,
+    });
+
+    assert.ok(
+      (await getEnvFileVariable(envFileName, "ADMIN_PRIVATE_KEY")) !== null,
+    );
+    assert.ok(
+      (await getEnvFileVariable(envFileName, "ALICE_PRIVATE_KEY")) !== null,
+    );
+    assert.ok(!firstLoad.admin.publicKey.equals(firstLoad.alice.publicKey));
+    assert.ok((await connection.getBalance(firstLoad.alice.publicKey)) > 0);
+
+    const secondLoad = await initializeKeypairs(connection, names, {
+      envFileName,
+    });
+    assert.ok(firstLoad.admin.publicKey.equals(secondLoad.admin.publicKey));
+    assert.ok(firstLoad.alice.publicKey.equals(secondLoad.alice.publicKey));
+
+    await deleteFile(envFileName);
+  });
+
+  test("saves named keypairs as files in a directory", async () => {
+    const keypairDirectory = `${TEMP_DIR}/initialize-keypairs`;
+    await rm(keypairDirectory, { recursive: true, force: true });
+    const { treasury } = await initializeKeypairs(connection, ["treasury"], {
+      keypairDirectory,
+      airdropAmount: null,
+    });
+    const loadedKeypair = await getKeypairFromFile(
+      `${keypairDirectory}/treasury.json`,
+    );
+    assert.ok(loadedKeypair.publicKey.equals(treasury.publicKey));
+  });
+
+  test("rejects names that aren't safe or would clash", async () => {
+    await assert.rejects(
+      () =>
+        initializeKeypairs(connection, ["../outside"], {
+          keypairDirectory: `${TEMP_DIR}/initialize-keypairs`,
+          airdropAmount: null,
+        }),
+      {
+        message: `Invalid keypair name '../outside', names can only have letters, numbers, '_' and '-'.`,
+      },
+    );
+    await assert.rejects(
+      () =>
+        initializeKeypairs(connection, ["alice-1", "alice_1"], {
+          airdropAmount: null,
+        }),
+      {
+        message: `Keypair names 'alice-1' and 'alice_1' would both be saved as 'ALICE_1_PRIVATE_KEY'.`,
+      },
+    );
+  });
+
+  test("throws the real error for keypair files that can't be loaded", async () => {
+    const keypairDirectory = `${TEMP_DIR}/initialize-keypairs-corrupt`;
+    await rm(keypairDirectory, { recursive: true, force: true });
+    await initializeKeypairs(connection, ["treasury"], {
+      keypairDirectory,
+      airdropAmount: null,
+    });
+    const keypairPath = `${keypairDirectory}/treasury.json`;
+    await writeFile(keypairPath, "not a keypair", { mode: 0o600 });
+
+    await assert.rejects(
+      () =>
+        initializeKeypairs(connection, ["treasury"], {
+          keypairDirectory,
+          airdropAmount: null,
+        }),
+      (error) => {
+        assert.ok(error instanceof Error);
+        assert.ok(!error.message.includes("already exists"));
+        return true;
+      },
+    );
+    // The file is left alone, rather than replaced with a new keypair
+    assert.equal(await readFile(keypairPath, "utf8"), "not a keypair");
+  });
+});
+
 describe("airdropIfRequired", () => {
   test("Checking the balance after airdropIfRequired", async () => {
     const keypair = Keypair.generate();
@@ -244,14 +950,17 @@ describe("airdropIfRequired", () => {
     assert.equal(originalBalance, 0);
     const lamportsToAirdrop = 1 * LAMPORTS_PER_SOL;
 
-    const newBalance = await airdropIfRequired(
+    const { balance, airdropped, signature, slot } = await airdropIfRequired(
       connection,
       keypair.publicKey,
       lamportsToAirdrop,
       1 * LAMPORTS_PER_SOL,
     );
 
-    assert.equal(newBalance, lamportsToAirdrop);
+    assert.equal(balance, lamportsToAirdrop);
+    assert.equal(airdropped, true);
+    assert.ok(signature);
+    assert.ok(slot > 0);
 
     const recipient = Keypair.generate();
 
@@ -281,7 +990,11 @@ describe("airdropIfRequired", () => {
       lamportsToAirdrop,
       500_000,
     );
-    const finalBalance = await airdropIfRequired(
+    const {
+      balance: finalBalance,
+      airdropped,
+      signature,
+    } = await airdropIfRequired(
       connection,
       keypair.publicKey,
       lamportsToAirdrop,
@@ -289,6 +1002,8 @@ describe("airdropIfRequired", () => {
     );
     // Check second airdrop didn't happen (since we only had 1 sol)
     assert.equal(finalBalance, 1 * lamportsToAirdrop);
+    assert.equal(airdropped, false);
+    assert.equal(signature, null);
   });
 
   test("airdropIfRequired does airdrop when necessary", async () => {
@@ -305,7 +1020,7 @@ describe("airdropIfRequired", () => {
       500_000,
     );
     // We only have 999_999_999 lamports, so we should need another airdrop
-    const finalBalance = await airdropIfRequired(
+    const { balance: finalBalance } = await airdropIfRequired(
       connection,
       keypair.publicKey,
       1 * LAMPORTS_PER_SOL,
@@ -314,11 +1029,429 @@ describe("airdropIfRequired", () => {
     // Check second airdrop happened
     assert.equal(finalBalance, 2 * LAMPORTS_PER_SOL - 1);
   });
-});
 
-describe("getExplorerLink", () => {
-  test("getExplorerLink works for a block on mainnet", () => {
-    const link = getExplorerLink("block", "242233124", "mainnet-beta");
+  test("confirms airdrops at the commitment asked for", async () => {
+    const keypair = Keypair.generate();
+    const connection = new Connection(LOCALHOST);
+    const { balance, airdropped } = await airdropIfRequired(
+      connection,
+      keypair.publicKey,
+      1 * LAMPORTS_PER_SOL,
+      1 * LAMPORTS_PER_SOL,
+      { commitment: "confirmed", timeout: 30_000 },
+    );
+    assert.equal(balance, 1 * LAMPORTS_PER_SOL);
+    assert.equal(airdropped, true);
+  });
+
+  test("throws an AirdropError if the airdrop isn't confirmed in time", async () => {
+    const keypair = Keypair.generate();
+    const connection = new Connection(LOCALHOST);
+    await assert.rejects(
+      () =>
+        airdropIfRequired(
+          connection,
+          keypair.publicKey,
+          1 * LAMPORTS_PER_SOL,
+          1 * LAMPORTS_PER_SOL,
+          { timeout: 1 },
+        ),
+      (error) => {
+        assert.ok(error instanceof AirdropError);
+        assert.equal(error.reason, "timeout");
+        return true;
+      },
+    );
+  });
+
+  test("retries airdrops when rate limited", async () => {
+    const keypair = Keypair.generate();
+    const connection = new Connection(LOCALHOST);
+    // Pretend the RPC server rate limits us twice, then lets the airdrop through
+    const requestAirdrop = connection.requestAirdrop.bind(connection);
+    let attempts = 0;
+    connection.requestAirdrop = async (publicKey, lamports) => {
+      attempts++;
+      if (attempts <= 2) {
+        throw new Error(
+          `airdrop to ${publicKey.toBase58()} failed: 429 Too Many Requests`,
+        );
+      }
+      return requestAirdrop(publicKey, lamports);
+    };
+
+    const { balance } = await airdropIfRequired(
+      connection,
+      keypair.publicKey,
+      1 * LAMPORTS_PER_SOL,
+      1 * LAMPORTS_PER_SOL,
+      { retryDelay: 1 },
+    );
+    assert.equal(attempts, 3);
+    assert.equal(balance, 1 * LAMPORTS_PER_SOL);
+  });
+
+  test("throws an AirdropError saying which limit was hit when retries run out", async () => {
+    const keypair = Keypair.generate();
+    const connection = new Connection(LOCALHOST);
+    connection.requestAirdrop = async () => {
+      throw new Error("429 Too Many Requests");
+    };
+
+    await assert.rejects(
+      () =>
+        airdropIfRequired(
+          connection,
+          keypair.publicKey,
+          1 * LAMPORTS_PER_SOL,
+          1 * LAMPORTS_PER_SOL,
+          { retries: 2, retryDelay: 1 },
+        ),
+      (error) => {
+        assert.ok(error instanceof AirdropError);
+        assert.equal(error.reason, "rate-limited");
+        assert.equal(error.attempts, 3);
+        assert.equal(
+          error.message,
+          `Airdrop to '${keypair.publicKey.toBase58()}' failed after 3 attempts: the RPC server is rate limiting requests (HTTP 429 Too Many Requests).`,
+        );
+        return true;
+      },
+    );
+  });
+
+  test("doesn't retry when the faucet's daily limit is reached", async () => {
+    const keypair = Keypair.generate();
+    const connection = new Connection(LOCALHOST);
+    let attempts = 0;
+    // What the devnet faucet says, as an HTTP 429
+    connection.requestAirdrop = async (publicKey) => {
+      attempts++;
+      throw new Error(
+        `airdrop to ${publicKey.toBase58()} failed: 429 Too Many Requests: {"jsonrpc":"2.0","error":{"code":429,"message":"You've either reached your airdrop limit today or the airdrop faucet has run dry. Please visit https://faucet.solana.com for alternate sources of test SOL"}}`,
+      );
+    };
+
+    await assert.rejects(
+      () =>
+        airdropIfRequired(
+          connection,
+          keypair.publicKey,
+          1 * LAMPORTS_PER_SOL,
+          1 * LAMPORTS_PER_SOL,
+          { retryDelay: 1 },
+        ),
+      (error) => {
+        assert.ok(error instanceof AirdropError);
+        assert.equal(error.reason, "airdrop-limit");
+        assert.equal(
+          error.message,
+          `Airdrop to '${keypair.publicKey.toBase58()}' failed: the faucet's airdrop request limit was reached.`,
+        );
+        return true;
+      },
+    );
+    assert.equal(attempts, 1);
+  });
+
+  test("doesn't retry airdrops that fail for other reasons", async () => {
+    const keypair = Keypair.generate();
+    const connection = new Connection(LOCALHOST);
+    let attempts = 0;
+    connection.requestAirdrop = async () => {
+      attempts++;
+      throw new Error("Invalid request");
+    };
+
+    await assert.rejects(
+      () =>
+        airdropIfRequired(
+          connection,
+          keypair.publicKey,
+          1 * LAMPORTS_PER_SOL,
+          1 * LAMPORTS_PER_SOL,
+          { retryDelay: 1 },
+        ),
+      (error) => {
+        assert.ok(error instanceof AirdropError);
+        assert.equal(error.reason, "failed");
+        return true;
+      },
+    );
+    assert.equal(attempts, 1);
+  });
+});
+
+describe("fundIfRequired", () => {
+  let funder: Keypair;
+
+  before(async () => {
+    funder = Keypair.generate();
+    await airdropIfRequired(
+      new Connection(LOCALHOST),
+      funder.publicKey,
+      10 * LAMPORTS_PER_SOL,
+      1 * LAMPORTS_PER_SOL,
+    );
+  });
+
+  test("sends SOL from the funder when the airdrop fails", async () => {
+    const keypair = Keypair.generate();
+    const connection = new Connection(LOCALHOST);
+    connection.requestAirdrop = async () => {
+      throw new Error("Faucet unavailable");
+    };
+
+    const { balance } = await fundIfRequired(
+      connection,
+      keypair.publicKey,
+      1 * LAMPORTS_PER_SOL,
+      1 * LAMPORTS_PER_SOL,
+      { funder },
+    );
+    assert.equal(balance, 1 * LAMPORTS_PER_SOL);
+  });
+
+  test("doesn't use the funder if the airdrop timed out, since it may still land", async () => {
+    const keypair = Keypair.generate();
+    const connection = new Connection(LOCALHOST);
+    await assert.rejects(
+      () =>
+        fundIfRequired(
+          connection,
+          keypair.publicKey,
+          1 * LAMPORTS_PER_SOL,
+          1 * LAMPORTS_PER_SOL,
+          { funder, timeout: 1 },
+        ),
+      (error) => {
+        assert.ok(error instanceof AirdropError);
+        assert.equal(error.reason, "timeout");
+        return true;
+      },
+    );
+  });
+
+  test("keeps the airdrop error if the funder fails too", async () => {
+    const keypair = Keypair.generate();
+    const connection = new Connection(LOCALHOST);
+    connection.requestAirdrop = async () => {
+      throw new Error("Faucet unavailable");
+    };
+
+    await assert.rejects(
+      () =>
+        fundIfRequired(
+          connection,
+          keypair.publicKey,
+          1 * LAMPORTS_PER_SOL,
+          1 * LAMPORTS_PER_SOL,
+          // A funder with no SOL
+          { funder: Keypair.generate() },
+        ),
+      (error) => {
+        assert.ok(error instanceof Error);
+        assert.ok(
+          error.message.startsWith(
+            `Airdrop to '${keypair.publicKey.toBase58()}' failed: the airdrop request failed. Sending SOL from the funder failed too:`,
+          ),
+        );
+        return true;
+      },
+    );
+  });
+
+  test("always uses the funder if asked to", async () => {
+    const keypair = Keypair.generate();
+    const connection = new Connection(LOCALHOST);
+    let airdropRequested = false;
+    connection.requestAirdrop = async () => {
+      airdropRequested = true;
+      throw new Error("The faucet shouldn't be used");
+    };
+
+    const { balance } = await fundIfRequired(
+      connection,
+      keypair.publicKey,
+      1 * LAMPORTS_PER_SOL,
+      1 * LAMPORTS_PER_SOL,
+      { funder: makeKeypairSigner(funder), alwaysUseFunder: true },
+    );
+    assert.equal(balance, 1 * LAMPORTS_PER_SOL);
+    assert.equal(airdropRequested, false);
+  });
+
+  test("uses the faucet if there's no funder", async () => {
+    const keypair = Keypair.generate();
+    const { balance } = await fundIfRequired(
+      new Connection(LOCALHOST),
+      keypair.publicKey,
+      1 * LAMPORTS_PER_SOL,
+      1 * LAMPORTS_PER_SOL,
+    );
+    assert.equal(balance, 1 * LAMPORTS_PER_SOL);
+  });
+});
+
+describe("airdropIfRequiredMany", () => {
+  test("only airdrops to the accounts that need it", async () => {
+    const connection = new Connection(LOCALHOST);
+    const [richKeypair, ...poorKeypairs] = makeKeypairs(4);
+    await airdropIfRequired(
+      connection,
+      richKeypair.publicKey,
+      2 * LAMPORTS_PER_SOL,
+      1 * LAMPORTS_PER_SOL,
+    );
+
+    const results = await airdropIfRequiredMany(
+      connection,
+      [richKeypair, ...poorKeypairs].map(({ publicKey }) => publicKey),
+      1 * LAMPORTS_PER_SOL,
+      1 * LAMPORTS_PER_SOL,
+      { concurrency: 2 },
+    );
+
+    assert.equal(results.length, 4);
+    assert.ok(results[0].publicKey.equals(richKeypair.publicKey));
+    assert.equal(results[0].balance, 2 * LAMPORTS_PER_SOL);
+    assert.equal(results[0].signature, null);
+    assert.equal(results[0].airdropped, false);
+    for (const result of results.slice(1)) {
+      assert.equal(result.balance, 1 * LAMPORTS_PER_SOL);
+      assert.equal(result.airdropped, true);
+      assert.ok(result.signature);
+    }
+  });
+
+  test("packs transfers from the funder into as few transactions as possible", async () => {
+    const connection = new Connection(LOCALHOST);
+    const funder = Keypair.generate();
+    await airdropIfRequired(
+      connection,
+      funder.publicKey,
+      10 * LAMPORTS_PER_SOL,
+      1 * LAMPORTS_PER_SOL,
+    );
+    const publicKeys = makeKeypairs(30).map(({ publicKey }) => publicKey);
+
+    const results = await airdropIfRequiredMany(
+      connection,
+      publicKeys,
+      0.1 * LAMPORTS_PER_SOL,
+      0.1 * LAMPORTS_PER_SOL,
+      { funder, alwaysUseFunder: true },
+    );
+
+    for (const result of results) {
+      assert.equal(result.balance, 0.1 * LAMPORTS_PER_SOL);
+    }
+    // Each transfer is much smaller than a transaction, so many fit in each
+    const signatures = new Set(results.map(({ signature }) => signature));
+    assert.ok(signatures.size < publicKeys.length / 10);
+  });
+
+  test("returns the error for airdrops that fail, and the results for the rest", async () => {
+    const connection = new Connection(LOCALHOST);
+    const [failingKeypair, ...otherKeypairs] = makeKeypairs(3);
+    const requestAirdrop = connection.requestAirdrop.bind(connection);
+    connection.requestAirdrop = async (publicKey, lamports) => {
+      if (publicKey.equals(failingKeypair.publicKey)) {
+        throw new Error("Invalid request");
+      }
+      return requestAirdrop(publicKey, lamports);
+    };
+
+    const results = await airdropIfRequiredMany(
+      connection,
+      [failingKeypair, ...otherKeypairs].map(({ publicKey }) => publicKey),
+      1 * LAMPORTS_PER_SOL,
+      1 * LAMPORTS_PER_SOL,
+    );
+
+    assert.ok(results[0].error instanceof AirdropError);
+    assert.equal(results[0].airdropped, false);
+    for (const result of results.slice(1)) {
+      assert.equal(result.error, null);
+      assert.equal(result.airdropped, true);
+      assert.ok(result.signature);
+    }
+  });
+
+  test("throws if concurrency is less than 1", async () => {
+    await assert.rejects(
+      () =>
+        airdropIfRequiredMany(
+          new Connection(LOCALHOST),
+          [Keypair.generate().publicKey],
+          1 * LAMPORTS_PER_SOL,
+          1 * LAMPORTS_PER_SOL,
+          { concurrency: 0 },
+        ),
+      {
+        message: "'concurrency' must be a whole number, at least 1, but was 0.",
+      },
+    );
+  });
+});
+
+describe("getClusterFromConnection", () => {
+  test("detects a local validator", async () => {
+    const cluster = await getClusterFromConnection(new Connection(LOCALHOST));
+    assert.equal(cluster, "localnet");
+  });
+
+  test("detects mainnet from its genesis hash", async () => {
+    // Any RPC URL, since we only care about the genesis hash
+    const connection = new Connection("http://rpc.example.com");
+    connection.getGenesisHash = async () =>
+      "5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d";
+    assert.equal(await getClusterFromConnection(connection), "mainnet-beta");
+  });
+
+  test("reports unknown remote clusters as custom", async () => {
+    const connection = new Connection("http://rpc.example.com");
+    connection.getGenesisHash = async () =>
+      Keypair.generate().publicKey.toBase58();
+    assert.equal(await getClusterFromConnection(connection), "custom");
+  });
+
+  test("airdrops on mainnet throw a clear error", async () => {
+    const connection = new Connection("http://rpc.example.com");
+    const keypair = Keypair.generate();
+    connection.getGenesisHash = async () =>
+      "5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d";
+    connection.getBalanceAndContext = async () => ({
+      context: { slot: 1 },
+      value: 0,
+    });
+    let airdropRequested = false;
+    connection.requestAirdrop = async () => {
+      airdropRequested = true;
+      return "";
+    };
+
+    await assert.rejects(
+      () =>
+        airdropIfRequired(
+          connection,
+          keypair.publicKey,
+          1 * LAMPORTS_PER_SOL,
+          1 * LAMPORTS_PER_SOL,
+        ),
+      (error) => {
+        assert.ok(error instanceof AirdropError);
+        assert.equal(error.reason, "mainnet");
+        return true;
+      },
+    );
+    assert.equal(airdropRequested, false);
+  });
+});
+
+describe("getExplorerLink", () => {
+  test("getExplorerLink works for a block on mainnet", () => {
+    const link = getExplorerLink("block", "242233124", "mainnet-beta");
     assert.equal(link, "https://explorer.solana.com/block/242233124");
   });
 
@@ -357,26 +1490,387 @@ describe("getExplorerLink", () => {
       "4nzNU7YxPtPsVzeg16oaZvLz4jMPtbAzavDfEFmemHNv93iYXKKYAaqBJzFCwEVxiULqTYYrbjPwQnA1d9ZCTELg",
       "mainnet-beta",
     );
-    assert.equal(
-      link,
-      "https://explorer.solana.com/tx/4nzNU7YxPtPsVzeg16oaZvLz4jMPtbAzavDfEFmemHNv93iYXKKYAaqBJzFCwEVxiULqTYYrbjPwQnA1d9ZCTELg",
+    assert.equal(
+      link,
+      "https://explorer.solana.com/tx/4nzNU7YxPtPsVzeg16oaZvLz4jMPtbAzavDfEFmemHNv93iYXKKYAaqBJzFCwEVxiULqTYYrbjPwQnA1d9ZCTELg",
+    );
+  });
+
+  test("getExplorerLink works for a block on mainnet", () => {
+    const link = getExplorerLink("block", "241889720", "mainnet-beta");
+    assert.equal(link, "https://explorer.solana.com/block/241889720");
+  });
+
+  test("getExplorerLink provides a localnet URL", () => {
+    const link = getExplorerLink(
+      "tx",
+      "2QC8BkDVZgaPHUXG9HuPw7aE5d6kN5DTRXLe2inT1NzurkYTCFhraSEo883CPNe18BZ2peJC1x1nojZ5Jmhs94pL",
+      "localnet",
+    );
+    assert.equal(
+      link,
+      "https://explorer.solana.com/tx/2QC8BkDVZgaPHUXG9HuPw7aE5d6kN5DTRXLe2inT1NzurkYTCFhraSEo883CPNe18BZ2peJC1x1nojZ5Jmhs94pL?cluster=custom&customUrl=http%3A%2F%2Flocalhost%3A8899",
+    );
+  });
+
+  test("getExplorerLink works for Solscan", () => {
+    const link = getExplorerLink(
+      "address",
+      "dDCQNnDmNbFVi8cQhKAgXhyhXeJ625tvwsunRyRc7c8",
+      "devnet",
+      { explorer: "solscan" },
+    );
+    assert.equal(
+      link,
+      "https://solscan.io/account/dDCQNnDmNbFVi8cQhKAgXhyhXeJ625tvwsunRyRc7c8?cluster=devnet",
+    );
+  });
+
+  test("getExplorerLink works for SolanaFM on localnet", () => {
+    const link = getExplorerLink(
+      "tx",
+      "2QC8BkDVZgaPHUXG9HuPw7aE5d6kN5DTRXLe2inT1NzurkYTCFhraSEo883CPNe18BZ2peJC1x1nojZ5Jmhs94pL",
+      "localnet",
+      { explorer: "solanafm" },
+    );
+    assert.equal(
+      link,
+      "https://solana.fm/tx/2QC8BkDVZgaPHUXG9HuPw7aE5d6kN5DTRXLe2inT1NzurkYTCFhraSEo883CPNe18BZ2peJC1x1nojZ5Jmhs94pL?cluster=localnet-solana",
+    );
+  });
+
+  test("getExplorerLink works for XRAY on mainnet", () => {
+    const link = getExplorerLink(
+      "address",
+      "dDCQNnDmNbFVi8cQhKAgXhyhXeJ625tvwsunRyRc7c8",
+      "mainnet-beta",
+      { explorer: "xray" },
+    );
+    assert.equal(
+      link,
+      "https://xray.helius.xyz/account/dDCQNnDmNbFVi8cQhKAgXhyhXeJ625tvwsunRyRc7c8",
+    );
+  });
+
+  test("getExplorerLink throws a nice error for clusters the explorer doesn't support", () => {
+    assert.throws(
+      () =>
+        getExplorerLink("block", "242233124", "localnet", {
+          explorer: "xray",
+        }),
+      { message: "XRAY doesn't support 'localnet' links." },
+    );
+  });
+
+  test("getExplorerLink uses the customUrl for custom clusters", () => {
+    const link = getExplorerLink("block", "242233124", "custom", {
+      customUrl: "https://rpc.example.com",
+    });
+    assert.equal(
+      link,
+      "https://explorer.solana.com/block/242233124?cluster=custom&customUrl=https%3A%2F%2Frpc.example.com",
+    );
+  });
+
+  test("getExplorerLink works with custom explorer templates", () => {
+    const link = getExplorerLink("tx", "abc", "devnet", {
+      explorer: {
+        name: "Example Explorer",
+        transaction: "https://explorer.example.com/transactions/{id}?tab=logs",
+        address: "https://explorer.example.com/accounts/{id}",
+        block: "https://explorer.example.com/blocks/{id}",
+        clusterSearchParams: {
+          "mainnet-beta": {},
+          devnet: { network: "devnet" },
+        },
+      },
+    });
+    assert.equal(
+      link,
+      "https://explorer.example.com/transactions/abc?tab=logs&network=devnet",
+    );
+  });
+
+  test("getExplorerLink makes custom links from RPC URLs", () => {
+    const link = getExplorerLink("block", "242233124", "http://docker-host:8898");
+    assert.equal(
+      link,
+      "https://explorer.solana.com/block/242233124?cluster=custom&customUrl=http%3A%2F%2Fdocker-host%3A8898",
+    );
+  });
+
+  test("getExplorerLink makes normal links for public RPC URLs", () => {
+    const link = getExplorerLink(
+      "block",
+      "242233124",
+      "https://api.devnet.solana.com",
+    );
+    assert.equal(
+      link,
+      "https://explorer.solana.com/block/242233124?cluster=devnet",
+    );
+  });
+
+  test("getExplorerLink uses the RPC URL of a connection", () => {
+    const link = getExplorerLink(
+      "block",
+      "242233124",
+      new Connection("http://localhost:8890"),
+    );
+    assert.equal(
+      link,
+      "https://explorer.solana.com/block/242233124?cluster=custom&customUrl=http%3A%2F%2Flocalhost%3A8890",
+    );
+    assert.throws(
+      () =>
+        getExplorerLink(
+          "block",
+          "242233124",
+          new Connection("http://localhost:8890"),
+          { explorer: "solanafm" },
+        ),
+      {
+        message: `SolanaFM only supports local validators at 'http://localhost:8899'.`,
+      },
+    );
+  });
+
+  test("getExplorerLinkForConnection works out the cluster from the connection", async () => {
+    // Eg, an RPC provider's URL for devnet
+    const connection = new Connection("https://rpc.example.com");
+    connection.getGenesisHash = async () =>
+      "EtWTRABZaYq6iMfeYKouRu166VL8xqp4nhTuxP9DdVkT";
+    const link = await getExplorerLinkForConnection(
+      "block",
+      "242233124",
+      connection,
+    );
+    assert.equal(
+      link,
+      "https://explorer.solana.com/block/242233124?cluster=devnet",
+    );
+
+    const localLink = await getExplorerLinkForConnection(
+      "block",
+      "242233124",
+      new Connection(LOCALHOST),
+    );
+    assert.equal(
+      localLink,
+      "https://explorer.solana.com/block/242233124?cluster=custom&customUrl=http%3A%2F%2F127.0.0.1%3A8899",
+    );
+  });
+
+  test("getExplorerLink makes inspector links for unsent transactions", () => {
+    const payer = Keypair.generate();
+    const message = new TransactionMessage({
+      payerKey: payer.publicKey,
+      recentBlockhash: PublicKey.default.toBase58(),
+      instructions: [
+        SystemProgram.transfer({
+          fromPubkey: payer.publicKey,
+          toPubkey: Keypair.generate().publicKey,
+          lamports: 1_000_000,
+        }),
+      ],
+    });
+    const transaction = new VersionedTransaction(message.compileToV0Message());
+    const expectedMessage = Buffer.from(
+      transaction.message.serialize(),
+    ).toString("base64");
+
+    // Not signed yet, so there are no signatures
+    const unsignedLink = new URL(
+      getExplorerLink("inspector", transaction, "devnet"),
+    );
+    assert.equal(unsignedLink.pathname, "/tx/inspector");
+    assert.equal(unsignedLink.searchParams.get("message"), expectedMessage);
+    assert.equal(unsignedLink.searchParams.get("signatures"), null);
+    assert.equal(unsignedLink.searchParams.get("cluster"), "devnet");
+
+    // TransactionMessages are compiled the same way
+    const messageLink = new URL(getExplorerLink("inspector", message, "devnet"));
+    assert.equal(messageLink.searchParams.get("message"), expectedMessage);
+
+    transaction.sign([payer]);
+    const signedLink = new URL(
+      getExplorerLink("inspector", transaction, "devnet"),
+    );
+    assert.equal(
+      signedLink.searchParams.get("signatures"),
+      JSON.stringify([base58.encode(transaction.signatures[0])]),
+    );
+  });
+
+  test("getExplorerLink makes inspector links for legacy transactions", () => {
+    const payer = Keypair.generate();
+    const transaction = new Transaction({
+      feePayer: payer.publicKey,
+      recentBlockhash: PublicKey.default.toBase58(),
+    }).add(
+      SystemProgram.transfer({
+        fromPubkey: payer.publicKey,
+        toPubkey: Keypair.generate().publicKey,
+        lamports: 1_000_000,
+      }),
+    );
+    transaction.sign(payer);
+
+    const link = new URL(getExplorerLink("inspector", transaction));
+    assert.equal(
+      link.searchParams.get("message"),
+      transaction.serializeMessage().toString("base64"),
+    );
+    assert.equal(
+      link.searchParams.get("signatures"),
+      JSON.stringify([base58.encode(transaction.signature as Buffer)]),
+    );
+  });
+});
+
+describe("parseExplorerLink", () => {
+  const address = "dDCQNnDmNbFVi8cQhKAgXhyhXeJ625tvwsunRyRc7c8";
+  const signature =
+    "4nzNU7YxPtPsVzeg16oaZvLz4jMPtbAzavDfEFmemHNv93iYXKKYAaqBJzFCwEVxiULqTYYrbjPwQnA1d9ZCTELg";
+
+  test("parseExplorerLink parses Solana Explorer links", () => {
+    assert.deepEqual(
+      parseExplorerLink(
+        `https://explorer.solana.com/tx/${signature}?cluster=devnet`,
+      ),
+      {
+        explorer: "solana-explorer",
+        linkType: "transaction",
+        id: signature,
+        cluster: "devnet",
+        customUrl: null,
+      },
+    );
+    assert.deepEqual(
+      parseExplorerLink("https://explorer.solana.com/block/242233124"),
+      {
+        explorer: "solana-explorer",
+        linkType: "block",
+        id: "242233124",
+        cluster: "mainnet-beta",
+        customUrl: null,
+      },
+    );
+  });
+
+  test("parseExplorerLink is the opposite of getExplorerLink", () => {
+    const explorers = ["solana-explorer", "solscan", "solanafm"] as const;
+    for (const explorer of explorers) {
+      for (const cluster of ["mainnet-beta", "devnet", "localnet"] as const) {
+        const link = getExplorerLink("address", address, cluster, {
+          explorer,
+        });
+        const parsed = parseExplorerLink(link);
+        assert.equal(parsed.explorer, explorer);
+        assert.equal(parsed.linkType, "address");
+        assert.equal(parsed.id, address);
+        assert.equal(parsed.cluster, cluster);
+      }
+    }
+
+    const xrayLink = getExplorerLink("tx", signature, "devnet", {
+      explorer: "xray",
+    });
+    const parsedXrayLink = parseExplorerLink(xrayLink);
+    assert.equal(parsedXrayLink.explorer, "xray");
+    assert.equal(parsedXrayLink.linkType, "transaction");
+    assert.equal(parsedXrayLink.cluster, "devnet");
+  });
+
+  test("parseExplorerLink tells localnet and custom clusters apart", () => {
+    const localLink = getExplorerLink(
+      "address",
+      address,
+      "http://127.0.0.1:8899",
+    );
+    assert.equal(parseExplorerLink(localLink).cluster, "localnet");
+    assert.equal(
+      parseExplorerLink(localLink).customUrl,
+      "http://127.0.0.1:8899",
+    );
+
+    const customLink = getExplorerLink(
+      "address",
+      address,
+      "https://rpc.example.com",
+      { explorer: "solscan" },
+    );
+    assert.equal(parseExplorerLink(customLink).cluster, "custom");
+    assert.equal(
+      parseExplorerLink(customLink).customUrl,
+      "https://rpc.example.com",
+    );
+  });
+
+  test("parseExplorerLink ignores www. and trailing slashes", () => {
+    const parsed = parseExplorerLink(
+      `https://www.solscan.io/account/${address}/?cluster=testnet`,
+    );
+    assert.equal(parsed.id, address);
+    assert.equal(parsed.cluster, "testnet");
+  });
+
+  test("parseExplorerLink ignores tabs after the id", () => {
+    const parsed = parseExplorerLink(
+      `https://explorer.solana.com/address/${address}/tokens?cluster=devnet`,
+    );
+    assert.equal(parsed.linkType, "address");
+    assert.equal(parsed.id, address);
+    assert.equal(parsed.cluster, "devnet");
+
+    const anchorAccount = parseExplorerLink(
+      `https://explorer.solana.com/address/${address}/anchor-account`,
     );
+    assert.equal(anchorAccount.id, address);
+    assert.equal(anchorAccount.cluster, "mainnet-beta");
   });
 
-  test("getExplorerLink works for a block on mainnet", () => {
-    const link = getExplorerLink("block", "241889720", "mainnet-beta");
-    assert.equal(link, "https://explorer.solana.com/block/241889720");
+  test("parseExplorerLink doesn't guess mainnet for unknown cluster params", () => {
+    const customWithoutUrl = `https://explorer.solana.com/tx/${signature}?cluster=custom`;
+    assert.throws(() => parseExplorerLink(customWithoutUrl), {
+      message: `Could not work out the cluster for Solana Explorer link '${customWithoutUrl}'.`,
+    });
+    const xrayTestnet = `https://xray.helius.xyz/tx/${signature}?network=testnet`;
+    assert.throws(() => parseExplorerLink(xrayTestnet), {
+      message: `Could not work out the cluster for XRAY link '${xrayTestnet}'.`,
+    });
   });
 
-  test("getExplorerLink provides a localnet URL", () => {
-    const link = getExplorerLink(
-      "tx",
-      "2QC8BkDVZgaPHUXG9HuPw7aE5d6kN5DTRXLe2inT1NzurkYTCFhraSEo883CPNe18BZ2peJC1x1nojZ5Jmhs94pL",
-      "localnet",
+  test("parseExplorerLink throws on invalid ids", () => {
+    assert.throws(
+      () => parseExplorerLink(`https://explorer.solana.com/tx/${address}`),
+      {
+        message: `'${address}' isn't a valid transaction signature.`,
+      },
     );
-    assert.equal(
-      link,
-      "https://explorer.solana.com/tx/2QC8BkDVZgaPHUXG9HuPw7aE5d6kN5DTRXLe2inT1NzurkYTCFhraSEo883CPNe18BZ2peJC1x1nojZ5Jmhs94pL?cluster=custom&customUrl=http%3A%2F%2Flocalhost%3A8899",
+    assert.throws(
+      () => parseExplorerLink("https://solscan.io/account/not-an-address"),
+      {
+        message: "'not-an-address' isn't a valid address.",
+      },
+    );
+    assert.throws(
+      () => parseExplorerLink("https://solana.fm/block/latest"),
+      {
+        message: "'latest' isn't a valid block number.",
+      },
+    );
+  });
+
+  test("parseExplorerLink throws on links it doesn't understand", () => {
+    assert.throws(() => parseExplorerLink("not a link"), {
+      message: "'not a link' isn't a URL.",
+    });
+    assert.throws(
+      () => parseExplorerLink(`https://example.com/address/${address}`),
+      {
+        message: `'https://example.com/address/${address}' isn't a link to a supported explorer.`,
+      },
     );
   });
 });
@@ -394,6 +1888,60 @@ describe("makeKeypairs", () => {
     const keypairs = makeKeypairs(3);
     assert.equal(keypairs.length, 3);
   });
+
+  test("makeKeypairs() with a seed makes the same keypairs every time", () => {
+    const firstKeypairs = makeKeypairs(3, { seed: "my-test" });
+    const secondKeypairs = makeKeypairs(3, { seed: "my-test" });
+    firstKeypairs.forEach((keypair, index) => {
+      assert.ok(keypair.publicKey.equals(secondKeypairs[index].publicKey));
+    });
+    // But each keypair is different
+    assert.ok(!firstKeypairs[0].publicKey.equals(firstKeypairs[1].publicKey));
+    // And a different seed makes different keypairs
+    const [otherKeypair] = makeKeypairs(1, { seed: "another-test" });
+    assert.ok(!firstKeypairs[0].publicKey.equals(otherKeypair.publicKey));
+  });
+});
+
+describe("grindKeypair", () => {
+  test("grindKeypair makes a keypair with the requested prefix and suffix", async () => {
+    let progressReports = 0;
+    const keypair = await grindKeypair({
+      startsWith: "a",
+      endsWith: "z",
+      ignoreCase: true,
+      threads: 2,
+      onProgress: () => progressReports++,
+    });
+    const address = keypair.publicKey.toBase58().toLowerCase();
+    assert.ok(address.startsWith("a"));
+    assert.ok(address.endsWith("z"));
+    assert.ok(progressReports > 0);
+  });
+
+  test("grindKeypair can be cancelled", async () => {
+    const controller = new AbortController();
+    setTimeout(() => controller.abort(), 100);
+    await assert.rejects(
+      () => grindKeypair({ startsWith: "zzzzzzzz", signal: controller.signal }),
+      { message: "Keypair grinding was cancelled" },
+    );
+  });
+
+  test("getExpectedGrindAttempts estimates attempts from the base58 alphabet", () => {
+    assert.equal(getExpectedGrindAttempts({ startsWith: "ab" }), 58 * 58);
+    // 'a' and 'A' both exist in base58, but only 'o' does (not 'O')
+    assert.equal(
+      getExpectedGrindAttempts({ startsWith: "ao", ignoreCase: true }),
+      29 * 58,
+    );
+  });
+
+  test("throws a nice error for characters that aren't in base58", () => {
+    assert.throws(() => getExpectedGrindAttempts({ startsWith: "0x" }), {
+      message: `Invalid character '0', addresses are base58 which doesn't use 0, O, I or l`,
+    });
+  });
 });
 
 describe("confirmTransaction", () => {
@@ -421,6 +1969,155 @@ describe("confirmTransaction", () => {
 
     await confirmTransaction(connection, transaction);
   });
+
+  test("confirmTransaction throws a TransactionFailedError for custom program errors", async () => {
+    const connection = new Connection(LOCALHOST);
+    const sender = Keypair.generate();
+    await airdropIfRequired(
+      connection,
+      sender.publicKey,
+      1 * LAMPORTS_PER_SOL,
+      1 * LAMPORTS_PER_SOL,
+    );
+
+    // More SOL than the sender has, skipping preflight so the transaction
+    // fails onchain
+    const transaction = await connection.sendTransaction(
+      new Transaction().add(
+        SystemProgram.transfer({
+          fromPubkey: sender.publicKey,
+          toPubkey: Keypair.generate().publicKey,
+          lamports: 2 * LAMPORTS_PER_SOL,
+        }),
+      ),
+      [sender],
+      { skipPreflight: true },
+    );
+
+    await assert.rejects(
+      () => confirmTransaction(connection, transaction),
+      (error) => {
+        assert.ok(error instanceof TransactionFailedError);
+        assert.equal(
+          error.message,
+          "Error in transaction: instruction index 0, custom program error 1",
+        );
+        assert.equal(error.variant, "InstructionError");
+        assert.equal(error.instructionIndex, 0);
+        assert.equal(error.instructionError, "Custom");
+        assert.equal(error.customErrorCode, 1);
+        return true;
+      },
+    );
+  });
+
+  // Confirms any transaction with the given TransactionError
+  const makeConnectionWithError = (err: unknown) => {
+    const connection = new Connection(LOCALHOST);
+    connection.getLatestBlockhash = async () => ({
+      blockhash: PublicKey.default.toBase58(),
+      lastValidBlockHeight: 1,
+    });
+    connection.confirmTransaction = async () => ({
+      context: { slot: 1 },
+      value: { err: err as object },
+    });
+    return connection;
+  };
+  const signature =
+    "4nzNU7YxPtPsVzeg16oaZvLz4jMPtbAzavDfEFmemHNv93iYXKKYAaqBJzFCwEVxiULqTYYrbjPwQnA1d9ZCTELg";
+
+  test("confirmTransaction decodes the runtime's own instruction errors", async () => {
+    const connection = makeConnectionWithError({
+      InstructionError: [2, "InvalidAccountData"],
+    });
+    await assert.rejects(
+      () => confirmTransaction(connection, signature),
+      (error) => {
+        assert.ok(error instanceof TransactionFailedError);
+        assert.equal(
+          error.message,
+          "Error in transaction: instruction index 2, invalid account data for instruction (InvalidAccountData)",
+        );
+        assert.equal(error.instructionIndex, 2);
+        assert.equal(error.instructionError, "InvalidAccountData");
+        assert.equal(error.customErrorCode, null);
+        return true;
+      },
+    );
+
+    const borshConnection = makeConnectionWithError({
+      InstructionError: [0, { BorshIoError: "Unexpected length of input" }],
+    });
+    await assert.rejects(() => confirmTransaction(borshConnection, signature), {
+      name: "TransactionFailedError",
+      message:
+        "Error in transaction: instruction index 0, failed to serialize or deserialize account data: Unexpected length of input (BorshIoError)",
+    });
+  });
+
+  test("confirmTransaction decodes transaction errors", async () => {
+    await assert.rejects(
+      () =>
+        confirmTransaction(
+          makeConnectionWithError("BlockhashNotFound"),
+          signature,
+        ),
+      (error) => {
+        assert.ok(error instanceof TransactionFailedError);
+        assert.equal(
+          error.message,
+          "Error in transaction: blockhash not found (BlockhashNotFound)",
+        );
+        assert.equal(error.variant, "BlockhashNotFound");
+        assert.equal(error.instructionIndex, null);
+        return true;
+      },
+    );
+
+    await assert.rejects(
+      () =>
+        confirmTransaction(
+          makeConnectionWithError({
+            InsufficientFundsForRent: { account_index: 1 },
+          }),
+          signature,
+        ),
+      (error) => {
+        assert.ok(error instanceof TransactionFailedError);
+        assert.equal(error.variant, "InsufficientFundsForRent");
+        assert.equal(error.accountIndex, 1);
+        return true;
+      },
+    );
+
+    await assert.rejects(
+      () =>
+        confirmTransaction(
+          makeConnectionWithError({ DuplicateInstruction: 3 }),
+          signature,
+        ),
+      (error) => {
+        assert.ok(error instanceof TransactionFailedError);
+        assert.equal(error.variant, "DuplicateInstruction");
+        assert.equal(error.instructionIndex, 3);
+        return true;
+      },
+    );
+  });
+
+  test("confirmTransaction still reports errors it doesn't know", async () => {
+    await assert.rejects(
+      () =>
+        confirmTransaction(
+          makeConnectionWithError({ SomeNewError: { detail: 1 } }),
+          signature,
+        ),
+      {
+        message: 'Unknown RPC error: {"SomeNewError":{"detail":1}}',
+      },
+    );
+  });
 });
 
 describe(`getLogs`, () => {
@@ -499,6 +2196,201 @@ describe("getSimulationComputeUnits", () => {
     // also worth reviewing why memo program seems to use so many CUs.
     assert.equal(computeUnitsSendSolAndSayThanks, 3888);
   });
+
+  test("getSimulationComputeUnits errors link to the transaction inspector", async () => {
+    const connection = new Connection(LOCALHOST);
+    const sender = Keypair.generate();
+    await airdropIfRequired(
+      connection,
+      sender.publicKey,
+      1 * LAMPORTS_PER_SOL,
+      1 * LAMPORTS_PER_SOL,
+    );
+    // More SOL than the sender has
+    const sendTooMuchSol = SystemProgram.transfer({
+      fromPubkey: sender.publicKey,
+      toPubkey: Keypair.generate().publicKey,
+      lamports: 2 * LAMPORTS_PER_SOL,
+    });
+
+    await assert.rejects(
+      () =>
+        getSimulationComputeUnits(
+          connection,
+          [sendTooMuchSol],
+          sender.publicKey,
+          [],
+        ),
+      (error) => {
+        assert.ok(error instanceof TransactionFailedError);
+        assert.equal(error.customErrorCode, 1);
+        assert.ok(
+          error.message.includes(
+            "Inspect the transaction at https://explorer.solana.com/tx/inspector?message=",
+          ),
+        );
+        return true;
+      },
+    );
+  });
+});
+
+describe("async signers", () => {
+  const keypair = Keypair.generate();
+  const message = new TextEncoder().encode("hello from the helpers tests");
+
+  const makeTestTransaction = () =>
+    new VersionedTransaction(
+      new TransactionMessage({
+        payerKey: keypair.publicKey,
+        recentBlockhash: PublicKey.default.toBase58(),
+        instructions: [
+          SystemProgram.transfer({
+            fromPubkey: keypair.publicKey,
+            toPubkey: Keypair.generate().publicKey,
+            lamports: 1_000_000,
+          }),
+        ],
+      }).compileToV0Message(),
+    );
+
+  const isValidSignature = (
+    signature: Uint8Array,
+    signedMessage: Uint8Array,
+  ) => ed25519.verify(signature, signedMessage, keypair.publicKey.toBytes());
+
+  test("makeKeypairSigner signs messages and transactions", async () => {
+    const signer = makeKeypairSigner(keypair);
+    assert.ok(isValidSignature(await signer.signMessage(message), message));
+
+    const transaction = await signer.signTransaction(makeTestTransaction());
+    assert.ok(
+      isValidSignature(
+        transaction.signatures[0],
+        transaction.message.serialize(),
+      ),
+    );
+  });
+
+  test("makeKeypairSigner signs legacy transactions without a global Buffer", async () => {
+    const signer = makeKeypairSigner(keypair);
+    const transaction = new Transaction({
+      feePayer: keypair.publicKey,
+      recentBlockhash: PublicKey.default.toBase58(),
+    }).add(
+      SystemProgram.transfer({
+        fromPubkey: keypair.publicKey,
+        toPubkey: Keypair.generate().publicKey,
+        lamports: 1_000_000,
+      }),
+    );
+
+    // Like a browser
+    const globalBuffer = globalThis.Buffer;
+    // @ts-ignore Buffer isn't optional in node's types
+    delete globalThis.Buffer;
+    try {
+      await signer.signTransaction(transaction);
+    } finally {
+      globalThis.Buffer = globalBuffer;
+    }
+    assert.ok(
+      isValidSignature(
+        transaction.signature as Uint8Array,
+        transaction.serializeMessage(),
+      ),
+    );
+  });
+
+  test("makeCommandSigner gets the secret key from a command", async () => {
+    const signer = await makeCommandSigner(
+      `echo ${base58.encode(keypair.secretKey)}`,
+    );
+    assert.ok(signer.publicKey.equals(keypair.publicKey));
+    assert.ok(isValidSignature(await signer.signMessage(message), message));
+  });
+
+  test("makeCommandSigner doesn't leak the secret key in errors", async () => {
+    const secretKey = base58.encode(keypair.secretKey);
+    await assert.rejects(
+      () => makeCommandSigner(`echo ${secretKey}; exit 1`),
+      (error) => {
+        assert.ok(error instanceof Error);
+        assert.equal(error.cause, undefined);
+        assert.ok(!error.message.includes(secretKey));
+        return true;
+      },
+    );
+  });
+
+  test("makeHttpSigner signs using a remote signer", async () => {
+    // A stand-in for a remote signer, see the README for the protocol
+    const server = createServer((request, response) => {
+      let body = "";
+      request.on("data", (chunk) => (body += chunk));
+      request.on("end", () => {
+        response.setHeader("Content-Type", "application/json");
+        if (request.method === "GET" && request.url === "/public-key") {
+          response.end(
+            JSON.stringify({ publicKey: keypair.publicKey.toBase58() }),
+          );
+          return;
+        }
+        if (request.method === "POST" && request.url === "/sign-message") {
+          const { message: encodedMessage } = JSON.parse(body);
+          const signature = ed25519.sign(
+            Buffer.from(encodedMessage, "base64"),
+            keypair.secretKey.slice(0, 32),
+          );
+          response.end(
+            JSON.stringify({
+              signature: Buffer.from(signature).toString("base64"),
+            }),
+          );
+          return;
+        }
+        response.statusCode = 404;
+        response.end();
+      });
+    });
+    await new Promise<void>((resolve) => server.listen(0, resolve));
+    const { port } = server.address() as AddressInfo;
+
+    try {
+      const signer = await makeHttpSigner(`http://127.0.0.1:${port}`);
+      assert.ok(signer.publicKey.equals(keypair.publicKey));
+      assert.ok(isValidSignature(await signer.signMessage(message), message));
+    } finally {
+      server.close();
+    }
+  });
+
+  test("makeAndSendAndConfirmTransaction sends transactions signed by async signers", async () => {
+    const connection = new Connection(LOCALHOST);
+    const sender = Keypair.generate();
+    await airdropIfRequired(
+      connection,
+      sender.publicKey,
+      1 * LAMPORTS_PER_SOL,
+      1 * LAMPORTS_PER_SOL,
+    );
+    const signer = makeKeypairSigner(sender);
+    const recipient = Keypair.generate().publicKey;
+
+    await makeAndSendAndConfirmTransaction(
+      connection,
+      [
+        SystemProgram.transfer({
+          fromPubkey: signer.publicKey,
+          toPubkey: recipient,
+          lamports: 1_000_000,
+        }),
+      ],
+      [signer],
+      signer,
+    );
+    assert.equal(await connection.getBalance(recipient), 1_000_000);
+  });
 });
 
 describe("createAccountsMintsAndTokenAccounts", () => {
@@ -555,3 +2447,224 @@ describe("createAccountsMintsAndTokenAccounts", () => {
     assert(Number(secondUserSecondTokenBalance.value.amount) === 1_000_000_000);
   });
 });
+
+describe("rotateKeypair", () => {
+  test("moves SOL and tokens to a new keypair and updates the env file", async () => {
+    const envFileName = ".env-unittest-rotatekeypair";
+    const envVariableName = "ROTATED_PRIVATE_KEY";
+    const payer = Keypair.generate();
+    const connection = new Connection(LOCALHOST);
+    await airdropIfRequired(
+      connection,
+      payer.publicKey,
+      100 * LAMPORTS_PER_SOL,
+      1 * LAMPORTS_PER_SOL,
+    );
+
+    const {
+      users: [oldKeypair],
+      mints: [mint],
+    } = await createAccountsMintsAndTokenAccounts(
+      [[1_000_000_000]],
+      1 * LAMPORTS_PER_SOL,
+      connection,
+      payer,
+    );
+    await writeFile(
+      envFileName,
+      `${envVariableName}=${JSON.stringify(Array.from(oldKeypair.secretKey))}\n`,
+    );
+
+    const { newKeypair, signatures } = await rotateKeypair(connection, {
+      envFileName,
+      envVariableName,
+    });
+    assert.ok(!oldKeypair.publicKey.equals(newKeypair.publicKey));
+    assert.ok(signatures.length > 0);
+
+    // Everything has moved to the new keypair
+    assert.equal(await connection.getBalance(oldKeypair.publicKey), 0);
+    const newBalance = await connection.getBalance(newKeypair.publicKey);
+    assert.ok(newBalance > 0.99 * LAMPORTS_PER_SOL);
+    const newTokenAccounts = await connection.getParsedTokenAccountsByOwner(
+      newKeypair.publicKey,
+      { mint: mint.publicKey },
+    );
+    assert.equal(
+      newTokenAccounts.value[0].account.data.parsed.info.tokenAmount.amount,
+      "1000000000",
+    );
+    const oldTokenAccounts = await connection.getParsedTokenAccountsByOwner(
+      oldKeypair.publicKey,
+      { mint: mint.publicKey },
+    );
+    assert.equal(oldTokenAccounts.value.length, 0);
+
+    // The env file has the new keypair, and the old one commented out
+    assert.equal(
+      await getEnvFileVariable(envFileName, envVariableName),
+      JSON.stringify(Array.from(newKeypair.secretKey)),
+    );
+    const envFileContents = await readFile(envFileName, "utf8");
+    assert.ok(
+      envFileContents.includes(
+        `# ${envVariableName}=${JSON.stringify(Array.from(oldKeypair.secretKey))}`,
+      ),
+    );
+    assert.ok(envFileContents.includes(oldKeypair.publicKey.toBase58()));
+
+    await deleteFile(envFileName);
+  });
+
+  test("keeps moving token accounts when one can't be moved", async () => {
+    const envFileName = ".env-unittest-rotatekeypair-frozen";
+    const envVariableName = "TEST_ROTATE_FROZEN_PRIVATE_KEY";
+    const payer = Keypair.generate();
+    const connection = new Connection(LOCALHOST);
+    await airdropIfRequired(
+      connection,
+      payer.publicKey,
+      100 * LAMPORTS_PER_SOL,
+      1 * LAMPORTS_PER_SOL,
+    );
+
+    const {
+      users: [oldKeypair],
+      mints: [mint],
+    } = await createAccountsMintsAndTokenAccounts(
+      [[1_000_000_000]],
+      1 * LAMPORTS_PER_SOL,
+      connection,
+      payer,
+    );
+    // A frozen token account can't be transferred from or closed
+    const frozenMint = await createMint(
+      connection,
+      payer,
+      payer.publicKey,
+      payer.publicKey,
+      0,
+    );
+    const frozenTokenAccount = await createAssociatedTokenAccount(
+      connection,
+      payer,
+      frozenMint,
+      oldKeypair.publicKey,
+    );
+    await mintTo(connection, payer, frozenMint, frozenTokenAccount, payer, 5);
+    await freezeAccount(
+      connection,
+      payer,
+      frozenTokenAccount,
+      frozenMint,
+      payer,
+    );
+    await writeFile(
+      envFileName,
+      `${envVariableName}=${JSON.stringify(Array.from(oldKeypair.secretKey))}\n`,
+    );
+
+    const { newKeypair, failedTokenAccounts } = await rotateKeypair(
+      connection,
+      { envFileName, envVariableName },
+    );
+
+    assert.equal(failedTokenAccounts.length, 1);
+    assert.ok(failedTokenAccounts[0].tokenAccount.equals(frozenTokenAccount));
+    assert.ok(failedTokenAccounts[0].mint.equals(frozenMint));
+    assert.ok(failedTokenAccounts[0].error instanceof Error);
+
+    // The other token account, and the SOL, still moved
+    const newTokenAccounts = await connection.getParsedTokenAccountsByOwner(
+      newKeypair.publicKey,
+      { mint: mint.publicKey },
+    );
+    assert.equal(
+      newTokenAccounts.value[0].account.data.parsed.info.tokenAmount.amount,
+      "1000000000",
+    );
+    assert.equal(await connection.getBalance(oldKeypair.publicKey), 0);
+
+    await deleteFile(envFileName);
+  });
+});
+
+describe("mintTokensIfRequired", () => {
+  test("mints the difference to reach the amount, only when below the minimum", async () => {
+    const connection = new Connection(LOCALHOST);
+    const payer = Keypair.generate();
+    await airdropIfRequired(
+      connection,
+      payer.publicKey,
+      10 * LAMPORTS_PER_SOL,
+      1 * LAMPORTS_PER_SOL,
+    );
+    // Token Extensions mint, where users[0] is the mint authority
+    const {
+      users: [mintAuthority],
+      mints: [mint],
+    } = await createAccountsMintsAndTokenAccounts(
+      [[1_000]],
+      1 * LAMPORTS_PER_SOL,
+      connection,
+      payer,
+    );
+
+    const firstResult = await mintTokensIfRequired(connection, {
+      mint: mint.publicKey,
+      owner: mintAuthority.publicKey,
+      minimum: 5_000,
+      amount: 10_000,
+      mintAuthority,
+    });
+    assert.equal(firstResult.minted, 9_000n);
+    assert.equal(firstResult.balance, 10_000n);
+    assert.ok(firstResult.signature);
+    const tokenBalance = await connection.getTokenAccountBalance(
+      firstResult.tokenAccount,
+    );
+    assert.equal(tokenBalance.value.amount, "10000");
+
+    const secondResult = await mintTokensIfRequired(connection, {
+      mint: mint.publicKey,
+      owner: mintAuthority.publicKey,
+      minimum: 5_000,
+      amount: 10_000,
+      mintAuthority,
+    });
+    assert.equal(secondResult.minted, 0n);
+    assert.equal(secondResult.balance, 10_000n);
+    assert.equal(secondResult.signature, null);
+  });
+
+  test("creates the token account for the original token program", async () => {
+    const connection = new Connection(LOCALHOST);
+    const mintAuthority = Keypair.generate();
+    await airdropIfRequired(
+      connection,
+      mintAuthority.publicKey,
+      1 * LAMPORTS_PER_SOL,
+      1 * LAMPORTS_PER_SOL,
+    );
+    const mint = await createMint(
+      connection,
+      mintAuthority,
+      mintAuthority.publicKey,
+      null,
+      6,
+      undefined,
+      undefined,
+      TOKEN_PROGRAM_ID,
+    );
+    const owner = Keypair.generate().publicKey;
+
+    const { tokenAccount, balance, minted } = await mintTokensIfRequired(
+      connection,
+      { mint, owner, minimum: 1_000_000, mintAuthority },
+    );
+    assert.equal(minted, 1_000_000n);
+    assert.equal(balance, 1_000_000n);
+    const tokenAccountInfo = await connection.getAccountInfo(tokenAccount);
+    assert.ok(tokenAccountInfo?.owner.equals(TOKEN_PROGRAM_ID));
+  });
+});
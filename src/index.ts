@@ -16,30 +16,363 @@ import {
   Signer,
   TransactionConfirmationStrategy,
   Commitment,
+  Transaction,
+  ParsedAccountData,
+  PACKET_DATA_SIZE,
 } from "@solana/web3.js";
 import base58 from "bs58";
+import { ed25519 } from "@noble/curves/ed25519";
+import { hmac } from "@noble/hashes/hmac";
+import { pbkdf2 } from "@noble/hashes/pbkdf2";
+import { sha256 } from "@noble/hashes/sha256";
+import { sha512 } from "@noble/hashes/sha512";
 import {
   TOKEN_PROGRAM_ID,
   MINT_SIZE,
   createAssociatedTokenAccountIdempotentInstruction,
+  createCloseAccountInstruction,
   createInitializeMint2Instruction,
   createMintToInstruction,
+  createTransferCheckedInstruction,
   getAssociatedTokenAddressSync,
   getMinimumBalanceForRentExemptMint,
   TOKEN_2022_PROGRAM_ID,
+  unpackAccount,
 } from "@solana/spl-token";
+import { BIP39_ENGLISH_WORDLIST } from "./bip39-english-wordlist";
 
-// Default value from Solana CLI
+// Default values from Solana CLI
 const DEFAULT_FILEPATH = "~/.config/solana/id.json";
+const DEFAULT_CLI_CONFIG_FILEPATH = "~/.config/solana/cli/config.yml";
+const DEFAULT_CLI_RPC_URL = "https://api.mainnet-beta.solana.com";
+const DEFAULT_CLI_COMMITMENT: Commitment = "confirmed";
 const DEFAULT_AIRDROP_AMOUNT = 1 * LAMPORTS_PER_SOL;
 const DEFAULT_MINIMUM_BALANCE = 0.5 * LAMPORTS_PER_SOL;
+// Connection also retries HTTP 429s itself, up to 5 times, unless it was
+// made with 'disableRetryOnRateLimit', so by default a rate limited RPC
+// server can get up to (5 + 1) * (5 + 1) = 36 airdrop requests
+const DEFAULT_AIRDROP_RETRIES = 5;
+const DEFAULT_AIRDROP_RETRY_DELAY = 1_000;
+const DEFAULT_MAX_AIRDROP_RETRY_DELAY = 30_000;
+const DEFAULT_AIRDROP_CONCURRENCY = 4;
+// "finalized" is slow but we must be absolutely sure
+// the airdrop has gone through
+const DEFAULT_AIRDROP_COMMITMENT: Commitment = "finalized";
+// The most accounts getMultipleAccountsInfo() will return at once
+const MAX_ACCOUNTS_PER_REQUEST = 100;
 const DEFAULT_ENV_KEYPAIR_VARIABLE_NAME = "PRIVATE_KEY";
+const DEFAULT_ENV_FILE_NAME = ".env";
+// BIP39 allows 12, 15, 18, 21 or 24 words
+const VALID_MNEMONIC_WORD_COUNTS = [12, 15, 18, 21, 24];
+// ed25519 only supports hardened derivation, see
+// https://github.com/satoshilabs/slips/blob/master/slip-0010.md
+const HARDENED_OFFSET = 0x80000000;
+// A 32 byte ed25519 seed, or the 64 byte seed and public key used by web3.js
+const SECRET_KEY_LENGTHS = [32, 64];
+const ENCRYPTED_KEYPAIR_FILE_VERSION = 1;
+// Takes around a second on a laptop, see
+// https://words.filippo.io/the-scrypt-parameters/
+const SCRYPT_PARAMS = { n: 2 ** 17, r: 8, p: 1 };
+// WebCrypto doesn't have scrypt, so we use PBKDF2 with OWASP's recommended iterations
+// https://cheatsheetseries.owasp.org/cheatsheets/Password_Storage_Cheat_Sheet.html#pbkdf2
+const PBKDF2_ITERATIONS = 600_000;
+const DEFAULT_INDEXED_DB_NAME = "solana-helpers";
+// What link() fails with on filesystems without hard links, like exFAT and
+// some Docker and SMB mounts
+const HARD_LINK_UNSUPPORTED_ERROR_CODES = [
+  "EPERM",
+  "ENOTSUP",
+  "EOPNOTSUPP",
+  "ENOSYS",
+];
 
 const log = console.log;
 
 const TOKEN_PROGRAM: typeof TOKEN_2022_PROGRAM_ID | typeof TOKEN_PROGRAM_ID =
   TOKEN_2022_PROGRAM_ID;
 
+// Descriptions from the validator's TransactionError enum, see
+// https://github.com/anza-xyz/agave/blob/master/sdk/src/transaction/error.rs
+const TRANSACTION_ERROR_DESCRIPTIONS = {
+  AccountInUse: "account in use",
+  AccountLoadedTwice: "account loaded twice",
+  AccountNotFound:
+    "attempt to debit an account but found no record of a prior credit",
+  ProgramAccountNotFound: "attempt to load a program that does not exist",
+  InsufficientFundsForFee: "insufficient funds for fee",
+  InvalidAccountForFee: "this account may not be used to pay transaction fees",
+  AlreadyProcessed: "this transaction has already been processed",
+  BlockhashNotFound: "blockhash not found",
+  InstructionError: "error processing instruction",
+  CallChainTooDeep: "loader call chain is too deep",
+  MissingSignatureForFee:
+    "transaction requires a fee but has no signature present",
+  InvalidAccountIndex: "transaction contains an invalid account reference",
+  SignatureFailure: "transaction did not pass signature verification",
+  InvalidProgramForExecution:
+    "this program may not be used for executing instructions",
+  SanitizeFailure: "transaction failed to sanitize accounts offsets correctly",
+  ClusterMaintenance:
+    "transactions are currently disabled due to cluster maintenance",
+  AccountBorrowOutstanding:
+    "transaction processing left an account with an outstanding borrowed reference",
+  WouldExceedMaxBlockCostLimit: "transaction would exceed max block cost limit",
+  UnsupportedVersion: "transaction version is unsupported",
+  InvalidWritableAccount:
+    "transaction loads a writable account that cannot be written",
+  WouldExceedMaxAccountCostLimit:
+    "transaction would exceed max account limit within the block",
+  WouldExceedAccountDataBlockLimit:
+    "transaction would exceed account data limit within the block",
+  TooManyAccountLocks: "transaction locked too many accounts",
+  AddressLookupTableNotFound:
+    "transaction loads an address table account that doesn't exist",
+  InvalidAddressLookupTableOwner:
+    "transaction loads an address table account with an invalid owner",
+  InvalidAddressLookupTableData:
+    "transaction loads an address table account with invalid data",
+  InvalidAddressLookupTableIndex:
+    "transaction address table lookup uses an invalid index",
+  InvalidRentPayingAccount:
+    "transaction leaves an account with a lower balance than rent-exempt minimum",
+  WouldExceedMaxVoteCostLimit: "transaction would exceed max vote cost limit",
+  WouldExceedAccountDataTotalLimit:
+    "transaction would exceed total account data limit",
+  DuplicateInstruction: "transaction contains a duplicate instruction",
+  InsufficientFundsForRent:
+    "transaction results in an account with insufficient funds for rent",
+  MaxLoadedAccountsDataSizeExceeded:
+    "transaction exceeded max loaded accounts data size cap",
+  InvalidLoadedAccountsDataSizeLimit:
+    "LoadedAccountsDataSizeLimit set for transaction must be greater than 0",
+  ResanitizationNeeded:
+    "sanitized transaction differed before/after feature activation, needs to be resanitized",
+  ProgramExecutionTemporarilyRestricted:
+    "execution of the program referenced by this instruction is temporarily restricted",
+  UnbalancedTransaction:
+    "sum of account balances before and after transaction do not match",
+  ProgramCacheHitMaxLimit: "program cache hit max limit",
+  CommitCancelled: "commit cancelled",
+};
+
+export type TransactionErrorVariant =
+  keyof typeof TRANSACTION_ERROR_DESCRIPTIONS;
+
+// Descriptions from the validator's InstructionError enum, see
+// https://github.com/anza-xyz/agave/blob/master/sdk/program/src/instruction.rs
+const INSTRUCTION_ERROR_DESCRIPTIONS = {
+  GenericError: "generic instruction error",
+  InvalidArgument: "invalid program argument",
+  InvalidInstructionData: "invalid instruction data",
+  InvalidAccountData: "invalid account data for instruction",
+  AccountDataTooSmall: "account data too small for instruction",
+  InsufficientFunds: "insufficient funds for instruction",
+  IncorrectProgramId: "incorrect program id for instruction",
+  MissingRequiredSignature: "missing required signature for instruction",
+  AccountAlreadyInitialized: "instruction requires an uninitialized account",
+  UninitializedAccount: "instruction requires an initialized account",
+  UnbalancedInstruction:
+    "sum of account balances before and after instruction do not match",
+  ModifiedProgramId:
+    "instruction illegally modified the program id of an account",
+  ExternalAccountLamportSpend:
+    "instruction spent from the balance of an account it does not own",
+  ExternalAccountDataModified:
+    "instruction modified data of an account it does not own",
+  ReadonlyLamportChange:
+    "instruction changed the balance of a read-only account",
+  ReadonlyDataModified: "instruction modified data of a read-only account",
+  DuplicateAccountIndex: "instruction contains duplicate accounts",
+  ExecutableModified: "instruction changed executable bit of an account",
+  RentEpochModified: "instruction modified rent epoch of an account",
+  NotEnoughAccountKeys: "insufficient account keys for instruction",
+  AccountDataSizeChanged:
+    "program other than the account's owner changed the size of the account data",
+  AccountNotExecutable: "instruction expected an executable account",
+  AccountBorrowFailed:
+    "instruction tries to borrow reference for an account which is already borrowed",
+  AccountBorrowOutstanding:
+    "instruction left account with an outstanding borrowed reference",
+  DuplicateAccountOutOfSync:
+    "instruction modifications of multiply-passed account differ",
+  Custom: "custom program error",
+  InvalidError: "program returned invalid error code",
+  ExecutableDataModified: "instruction changed executable accounts data",
+  ExecutableLamportChange:
+    "instruction changed the balance of an executable account",
+  ExecutableAccountNotRentExempt: "executable accounts must be rent exempt",
+  UnsupportedProgramId: "unsupported program id",
+  CallDepth: "cross-program invocation call depth too deep",
+  MissingAccount: "an account required by the instruction is missing",
+  ReentrancyNotAllowed:
+    "cross-program invocation reentrancy not allowed for this instruction",
+  MaxSeedLengthExceeded:
+    "length of the seed is too long for address generation",
+  InvalidSeeds: "provided seeds do not result in a valid address",
+  InvalidRealloc: "failed to reallocate account data",
+  ComputationalBudgetExceeded: "computational budget exceeded",
+  PrivilegeEscalation:
+    "cross-program invocation with unauthorized signer or writable account",
+  ProgramEnvironmentSetupFailure:
+    "failed to create program execution environment",
+  ProgramFailedToComplete: "program failed to complete",
+  ProgramFailedToCompile: "program failed to compile",
+  Immutable: "account is immutable",
+  IncorrectAuthority: "incorrect authority provided",
+  BorshIoError: "failed to serialize or deserialize account data",
+  AccountNotRentExempt:
+    "an account does not have enough lamports to be rent-exempt",
+  InvalidAccountOwner: "invalid account owner",
+  ArithmeticOverflow: "program arithmetic overflowed",
+  UnsupportedSysvar: "unsupported sysvar",
+  IllegalOwner: "provided owner is not allowed",
+  MaxAccountsDataAllocationsExceeded:
+    "accounts data allocations exceeded the maximum allowed per transaction",
+  MaxAccountsExceeded: "max accounts exceeded",
+  MaxInstructionTraceLengthExceeded: "max instruction trace length exceeded",
+  BuiltinProgramsMustConsumeComputeUnits:
+    "builtin programs must consume compute units",
+};
+
+export type InstructionErrorVariant =
+  keyof typeof INSTRUCTION_ERROR_DESCRIPTIONS;
+
+export interface TransactionFailedErrorDetails {
+  // For 'InstructionError' and 'DuplicateInstruction'
+  instructionIndex?: number | null;
+  instructionError?: InstructionErrorVariant | null;
+  // The program's own error code, for 'Custom' instruction errors
+  customErrorCode?: number | null;
+  // For 'InsufficientFundsForRent' and 'ProgramExecutionTemporarilyRestricted'
+  accountIndex?: number | null;
+}
+
+// A transaction that failed, decoded from the RPC server's TransactionError
+export class TransactionFailedError extends Error {
+  variant: TransactionErrorVariant;
+  instructionIndex: number | null;
+  instructionError: InstructionErrorVariant | null;
+  customErrorCode: number | null;
+  accountIndex: number | null;
+
+  constructor(
+    message: string,
+    variant: TransactionErrorVariant,
+    details: TransactionFailedErrorDetails = {},
+  ) {
+    super(message);
+    this.name = "TransactionFailedError";
+    this.variant = variant;
+    this.instructionIndex = details.instructionIndex ?? null;
+    this.instructionError = details.instructionError ?? null;
+    this.customErrorCode = details.customErrorCode ?? null;
+    this.accountIndex = details.accountIndex ?? null;
+  }
+}
+
+// Enum variants without data are serialized as strings, and variants with
+// data as an object with a single key, eg { "InstructionError": [0, ...] }
+const getVariantAndData = (
+  serializedEnum: unknown,
+): { variant: string; data: unknown } | null => {
+  if (typeof serializedEnum === "string") {
+    return { variant: serializedEnum, data: null };
+  }
+  if (serializedEnum && typeof serializedEnum === "object") {
+    const entries = Object.entries(serializedEnum);
+    if (entries.length === 1) {
+      const [[variant, data]] = entries;
+      return { variant, data };
+    }
+  }
+  return null;
+};
+
+const decodeInstructionError = (
+  instructionIndex: number,
+  serializedInstructionError: unknown,
+): TransactionFailedError | null => {
+  const variantAndData = getVariantAndData(serializedInstructionError);
+  if (
+    !variantAndData ||
+    !Object.hasOwn(INSTRUCTION_ERROR_DESCRIPTIONS, variantAndData.variant)
+  ) {
+    return null;
+  }
+  const instructionError = variantAndData.variant as InstructionErrorVariant;
+  const { data } = variantAndData;
+  if (instructionError === "Custom") {
+    return new TransactionFailedError(
+      `Error in transaction: instruction index ${instructionIndex}, custom program error ${data}`,
+      "InstructionError",
+      { instructionIndex, instructionError, customErrorCode: Number(data) },
+    );
+  }
+  let description = INSTRUCTION_ERROR_DESCRIPTIONS[instructionError];
+  // BorshIoError includes the serialization error
+  if (typeof data === "string" && data) {
+    description += `: ${data}`;
+  }
+  return new TransactionFailedError(
+    `Error in transaction: instruction index ${instructionIndex}, ${description} (${instructionError})`,
+    "InstructionError",
+    { instructionIndex, instructionError },
+  );
+};
+
+// Turn the RPC server's TransactionError into a TransactionFailedError
+const decodeTransactionError = (
+  transactionError: unknown,
+): TransactionFailedError | null => {
+  const variantAndData = getVariantAndData(transactionError);
+  if (
+    !variantAndData ||
+    !Object.hasOwn(TRANSACTION_ERROR_DESCRIPTIONS, variantAndData.variant)
+  ) {
+    return null;
+  }
+  const variant = variantAndData.variant as TransactionErrorVariant;
+  const { data } = variantAndData;
+  const description = TRANSACTION_ERROR_DESCRIPTIONS[variant];
+
+  // An instruction error looks like:
+  // [
+  //   1,
+  //   {
+  //     "Custom": 1
+  //   }
+  // ]
+  // or [1, "InvalidAccountData"] for the runtime's own errors
+  // See also https://solana.stackexchange.com/a/931/294
+  if (variant === "InstructionError") {
+    if (!Array.isArray(data) || data.length !== 2) {
+      return null;
+    }
+    return decodeInstructionError(data[0], data[1]);
+  }
+  if (variant === "DuplicateInstruction" && typeof data === "number") {
+    return new TransactionFailedError(
+      `Error in transaction: instruction index ${data}, ${description} (${variant})`,
+      variant,
+      { instructionIndex: data },
+    );
+  }
+  // InsufficientFundsForRent and ProgramExecutionTemporarilyRestricted
+  // look like { "account_index": 1 }
+  if (data && typeof data === "object" && "account_index" in data) {
+    const accountIndex = Number(data.account_index);
+    return new TransactionFailedError(
+      `Error in transaction: account index ${accountIndex}, ${description} (${variant})`,
+      variant,
+      { accountIndex },
+    );
+  }
+  return new TransactionFailedError(
+    `Error in transaction: ${description} (${variant})`,
+    variant,
+  );
+};
+
 const getErrorFromRPCResponse = (
   rpcResponse: RpcResponseAndContext<
     SignatureResult | SimulatedTransactionResponse
@@ -54,28 +387,12 @@ const getErrorFromRPCResponse = (
     // Can be a string or an object (literally just {}, no further typing is provided by the library)
     // https://github.com/solana-labs/solana-web3.js/blob/4436ba5189548fc3444a9f6efb51098272926945/packages/library-legacy/src/connection.ts#L2930
     // TODO: if still occurs in web3.js 2 (unlikely), fix it.
-    if (typeof error === "object") {
-      const errorKeys = Object.keys(error);
-      if (errorKeys.length === 1) {
-        if (errorKeys[0] !== "InstructionError") {
-          throw new Error(`Unknown RPC error: ${error}`);
-        }
-        // @ts-ignore due to missing typing information mentioned above.
-        const instructionError = error["InstructionError"];
-        // An instruction error is a custom program error and looks like:
-        // [
-        //   1,
-        //   {
-        //     "Custom": 1
-        //   }
-        // ]
-        // See also https://solana.stackexchange.com/a/931/294
-        throw new Error(
-          `Error in transaction: instruction index ${instructionError[0]}, custom program error ${instructionError[1]["Custom"]}`,
-        );
-      }
+    const transactionFailedError = decodeTransactionError(error);
+    if (transactionFailedError) {
+      throw transactionFailedError;
     }
-    throw Error(error.toString());
+    // Probably a variant added in a newer validator
+    throw new Error(`Unknown RPC error: ${JSON.stringify(error)}`);
   }
 };
 
@@ -100,213 +417,2623 @@ export const getCustomErrorMessage = (
   return possibleProgramErrors[errorNumber] || null;
 };
 
+// The first block of each cluster, which never changes, so is a reliable
+// way to tell which cluster an RPC server is for
+const CLUSTER_GENESIS_HASHES: Record<string, Cluster> = {
+  "5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d": "mainnet-beta",
+  "EtWTRABZaYq6iMfeYKouRu166VL8xqp4nhTuxP9DdVkT": "devnet",
+  "4uhcVJyU9pJkvQyS88uRDiswHXSCkY3zQawwpjk2NsNY": "testnet",
+};
+
+const LOCAL_HOSTNAMES = ["localhost", "127.0.0.1", "0.0.0.0", "[::1]"];
+
+// 'custom' is any other cluster, eg, a private cluster or a fork
+export type ClusterName = Cluster | "localnet" | "custom";
+
+const clustersByConnection = new WeakMap<
+  Connection,
+  Promise<ClusterName>
+>();
+
+export const getClusterFromConnection = (
+  connection: Connection,
+): Promise<ClusterName> => {
+  // The genesis hash won't change, so only ask once per connection
+  let cluster = clustersByConnection.get(connection);
+  if (!cluster) {
+    cluster = connection.getGenesisHash().then((genesisHash) => {
+      const knownCluster = CLUSTER_GENESIS_HASHES[genesisHash];
+      if (knownCluster) {
+        return knownCluster;
+      }
+      const { hostname } = new URL(connection.rpcEndpoint);
+      return LOCAL_HOSTNAMES.includes(hostname) ? "localnet" : "custom";
+    });
+    // Don't cache failures, so we can try again
+    cluster.catch(() => clustersByConnection.delete(connection));
+    clustersByConnection.set(connection, cluster);
+  }
+  return cluster;
+};
+
 const encodeURL = (baseUrl: string, searchParams: Record<string, string>) => {
   // This was a little new to me, but it's the
   // recommended way to build URLs with query params
   // (and also means you don't have to do any encoding)
   // https://developer.mozilla.org/en-US/docs/Web/API/URLSearchParams
   const url = new URL(baseUrl);
-  url.search = new URLSearchParams(searchParams).toString();
+  // Add to, rather than replace, any search params already in the URL
+  for (const [name, value] of Object.entries(searchParams)) {
+    url.searchParams.set(name, value);
+  }
   return url.toString();
 };
 
+// How to make links for a block explorer
+export interface ExplorerTemplate {
+  name: string;
+  // URLs for each link type, '{id}' is replaced with the transaction
+  // signature, address, or block number
+  transaction: string;
+  address: string;
+  block: string;
+  // For explorers with a transaction inspector, which takes 'message' and
+  // 'signatures' search params, like Solana Explorer's
+  inspector?: string;
+  // The search params for each cluster the explorer supports.
+  // '{customUrl}' is replaced with the RPC URL for localnet and custom clusters.
+  clusterSearchParams: Partial<Record<ClusterName, Record<string, string>>>;
+}
+
+export type Explorer = "solana-explorer" | "solscan" | "solanafm" | "xray";
+
+const EXPLORERS: Record<Explorer, ExplorerTemplate> = {
+  "solana-explorer": {
+    name: "Solana Explorer",
+    transaction: "https://explorer.solana.com/tx/{id}",
+    address: "https://explorer.solana.com/address/{id}",
+    block: "https://explorer.solana.com/block/{id}",
+    inspector: "https://explorer.solana.com/tx/inspector",
+    clusterSearchParams: {
+      "mainnet-beta": {},
+      devnet: { cluster: "devnet" },
+      testnet: { cluster: "testnet" },
+      // localnet technically isn't a cluster, so requires special handling
+      localnet: { cluster: "custom", customUrl: "{customUrl}" },
+      custom: { cluster: "custom", customUrl: "{customUrl}" },
+    },
+  },
+  solscan: {
+    name: "Solscan",
+    transaction: "https://solscan.io/tx/{id}",
+    address: "https://solscan.io/account/{id}",
+    block: "https://solscan.io/block/{id}",
+    clusterSearchParams: {
+      "mainnet-beta": {},
+      devnet: { cluster: "devnet" },
+      testnet: { cluster: "testnet" },
+      localnet: { cluster: "custom", customUrl: "{customUrl}" },
+      custom: { cluster: "custom", customUrl: "{customUrl}" },
+    },
+  },
+  // SolanaFM only supports local validators on the default port
+  solanafm: {
+    name: "SolanaFM",
+    transaction: "https://solana.fm/tx/{id}",
+    address: "https://solana.fm/address/{id}",
+    block: "https://solana.fm/block/{id}",
+    clusterSearchParams: {
+      "mainnet-beta": {},
+      devnet: { cluster: "devnet-solana" },
+      testnet: { cluster: "testnet-solana" },
+      localnet: { cluster: "localnet-solana" },
+    },
+  },
+  xray: {
+    name: "XRAY",
+    transaction: "https://xray.helius.xyz/tx/{id}",
+    address: "https://xray.helius.xyz/account/{id}",
+    block: "https://xray.helius.xyz/block/{id}",
+    clusterSearchParams: {
+      "mainnet-beta": {},
+      devnet: { network: "devnet" },
+    },
+  },
+};
+
+const DEFAULT_LOCALNET_URL = "http://localhost:8899";
+
+export interface GetExplorerLinkOptions {
+  // One of the built in explorers, or a template for any other explorer
+  explorer?: Explorer | ExplorerTemplate;
+  // The RPC URL for localnet (if it's not the default) or custom clusters
+  customUrl?: string;
+}
+
+export type ExplorerLinkType =
+  | "transaction"
+  | "tx"
+  | "address"
+  | "block"
+  | "inspector";
+
+// Transactions that haven't been sent yet, for the transaction inspector
+export type InspectableTransaction =
+  | VersionedTransaction
+  | Transaction
+  | TransactionMessage;
+
+const getInspectorSearchParams = (
+  transaction: InspectableTransaction | string,
+): Record<string, string> => {
+  // Already a base64 encoded message
+  if (typeof transaction === "string") {
+    return { message: transaction };
+  }
+
+  let message: Uint8Array;
+  let signatures: Array<Uint8Array | null> = [];
+  if ("serializeMessage" in transaction) {
+    // Legacy transaction
+    message = transaction.serializeMessage();
+    signatures = transaction.signatures.map(({ signature }) => signature);
+  } else if ("payerKey" in transaction) {
+    // Not compiled or signed yet
+    message = transaction.compileToV0Message().serialize();
+  } else {
+    message = transaction.message.serialize();
+    signatures = transaction.signatures;
+  }
+
+  const searchParams: Record<string, string> = {
+    message: encodeBase64(message),
+  };
+  // The inspector matches signatures to signers by their position, so
+  // partly signed transactions are shown without any signatures.
+  // Unsigned versioned transactions have signatures of all zeros.
+  const isFullySigned =
+    signatures.length > 0 &&
+    signatures.every((signature) => signature?.some((byte) => byte !== 0));
+  if (isFullySigned) {
+    searchParams.signatures = JSON.stringify(
+      signatures.map((signature) => base58.encode(signature as Uint8Array)),
+    );
+  }
+  return searchParams;
+};
+
+const fillTemplate = (template: string, values: Record<string, string>) =>
+  template.replace(/\{(\w+)\}/g, (placeholder, name) =>
+    name in values ? values[name] : placeholder,
+  );
+
+const CLUSTER_NAMES: Array<ClusterName> = [
+  "mainnet-beta",
+  "devnet",
+  "testnet",
+  "localnet",
+  "custom",
+];
+
+// The public RPC servers, so we can make a normal link rather than a
+// custom one for them
+const PUBLIC_RPC_HOSTNAMES: Record<string, Cluster> = {
+  "api.mainnet-beta.solana.com": "mainnet-beta",
+  "api.devnet.solana.com": "devnet",
+  "api.testnet.solana.com": "testnet",
+};
+
+const isLocalUrl = (rpcUrl: string) =>
+  LOCAL_HOSTNAMES.includes(new URL(rpcUrl).hostname);
+
+// Work out the cluster, and the RPC URL for localnet and custom clusters,
+// from a cluster name, RPC URL or connection
+const getClusterAndCustomUrl = (
+  clusterOrRpcUrl: ClusterName | string | Connection,
+  customUrl?: string,
+): { cluster: ClusterName; customUrl?: string } => {
+  if (typeof clusterOrRpcUrl !== "string") {
+    clusterOrRpcUrl = clusterOrRpcUrl.rpcEndpoint;
+  }
+  if (CLUSTER_NAMES.includes(clusterOrRpcUrl as ClusterName)) {
+    return { cluster: clusterOrRpcUrl as ClusterName, customUrl };
+  }
+
+  let url: URL;
+  try {
+    url = new URL(clusterOrRpcUrl);
+  } catch (error) {
+    throw new Error(
+      `'${clusterOrRpcUrl}' isn't a cluster name (${CLUSTER_NAMES.join(", ")}) or an RPC URL.`,
+    );
+  }
+  const publicCluster = PUBLIC_RPC_HOSTNAMES[url.hostname];
+  if (publicCluster) {
+    return { cluster: publicCluster };
+  }
+  return {
+    cluster: isLocalUrl(clusterOrRpcUrl) ? "localnet" : "custom",
+    customUrl: clusterOrRpcUrl,
+  };
+};
+
+// Explorers that don't take a custom URL can only link to the default
+// local validator
+const isDefaultLocalnetUrl = (rpcUrl: string) =>
+  isLocalUrl(rpcUrl) && new URL(rpcUrl).port === "8899";
+
 export const getExplorerLink = (
-  linkType: "transaction" | "tx" | "address" | "block",
-  id: string,
-  cluster: Cluster | "localnet" = "mainnet-beta",
+  linkType: ExplorerLinkType,
+  // The transaction for 'inspector' links, otherwise the signature,
+  // address or block
+  id: string | InspectableTransaction,
+  // A cluster name, or an RPC URL or connection for localnet or custom clusters
+  clusterOrRpcUrl: ClusterName | string | Connection = "mainnet-beta",
+  options?: GetExplorerLinkOptions,
 ): string => {
+  const { explorer = "solana-explorer" } = options || {};
+  const template =
+    typeof explorer === "string" ? EXPLORERS[explorer] : explorer;
+  if (!template) {
+    throw new Error(`Unknown explorer '${explorer}'.`);
+  }
+
+  const { cluster, customUrl: rpcUrl } = getClusterAndCustomUrl(
+    clusterOrRpcUrl,
+    options?.customUrl,
+  );
+  const clusterSearchParams = template.clusterSearchParams[cluster];
+  if (!clusterSearchParams) {
+    throw new Error(`${template.name} doesn't support '${cluster}' links.`);
+  }
+  const customUrl =
+    cluster === "localnet" ? rpcUrl || DEFAULT_LOCALNET_URL : rpcUrl;
+  if (cluster === "custom" && !customUrl) {
+    throw new Error("Please provide a 'customUrl' for custom clusters.");
+  }
+  const usesCustomUrl = Object.values(clusterSearchParams).some((value) =>
+    value.includes("{customUrl}"),
+  );
+  if (
+    cluster === "localnet" &&
+    !usesCustomUrl &&
+    !isDefaultLocalnetUrl(customUrl as string)
+  ) {
+    throw new Error(
+      `${template.name} only supports local validators at '${DEFAULT_LOCALNET_URL}'.`,
+    );
+  }
+
   const searchParams: Record<string, string> = {};
-  if (cluster !== "mainnet-beta") {
-    if (cluster === "localnet") {
-      // localnet technically isn't a cluster, so requires special handling
-      searchParams["cluster"] = "custom";
-      searchParams["customUrl"] = "http://localhost:8899";
-    } else {
-      searchParams["cluster"] = cluster;
+  for (const [name, value] of Object.entries(clusterSearchParams)) {
+    searchParams[name] = fillTemplate(value, { customUrl: customUrl || "" });
+  }
+
+  if (linkType === "inspector") {
+    if (!template.inspector) {
+      throw new Error(`${template.name} doesn't have a transaction inspector.`);
     }
+    return encodeURL(template.inspector, {
+      ...getInspectorSearchParams(id),
+      ...searchParams,
+    });
   }
-  let baseUrl: string = "";
-  if (linkType === "address") {
-    baseUrl = `https://explorer.solana.com/address/${id}`;
+  if (typeof id !== "string") {
+    throw new Error(
+      `'${linkType}' links need a string, only 'inspector' links take a transaction.`,
+    );
   }
-  if (linkType === "transaction" || linkType === "tx") {
-    baseUrl = `https://explorer.solana.com/tx/${id}`;
+  const baseUrl = fillTemplate(
+    template[linkType === "tx" ? "transaction" : linkType],
+    { id },
+  );
+  return encodeURL(baseUrl, searchParams);
+};
+
+// Like getExplorerLink(), but works out the cluster from the connection,
+// so RPC providers' URLs for public clusters get normal links too
+export const getExplorerLinkForConnection = async (
+  linkType: ExplorerLinkType,
+  id: string | InspectableTransaction,
+  connection: Connection,
+  options?: GetExplorerLinkOptions,
+): Promise<string> => {
+  const cluster = await getClusterFromConnection(connection);
+  if (cluster === "localnet" || cluster === "custom") {
+    return getExplorerLink(linkType, id, connection, options);
   }
+  return getExplorerLink(linkType, id, cluster, options);
+};
+
+export interface ParsedExplorerLink {
+  explorer: Explorer;
+  linkType: "transaction" | "address" | "block";
+  id: string;
+  cluster: ClusterName;
+  // The RPC URL for localnet and custom clusters
+  customUrl: string | null;
+}
+
+// Explorers use 'www.' and trailing slashes interchangeably
+const normalizeExplorerUrl = (url: URL) => {
+  const hostname = url.hostname.replace(/^www\./, "");
+  const pathname = url.pathname.replace(/\/$/, "");
+  return `${url.protocol}//${hostname}${pathname}`;
+};
+
+// Check the id is the right kind of id for the link, so a mangled link
+// doesn't end up in a script
+const checkExplorerLinkId = (
+  linkType: ParsedExplorerLink["linkType"],
+  id: string,
+) => {
   if (linkType === "block") {
-    baseUrl = `https://explorer.solana.com/block/${id}`;
+    if (!/^\d+$/.test(id)) {
+      throw new Error(`'${id}' isn't a valid block number.`);
+    }
+    return;
+  }
+  const expectedLength = linkType === "transaction" ? 64 : 32;
+  let bytes: Uint8Array | null = null;
+  try {
+    bytes = base58.decode(id);
+  } catch (error) {
+    // Not base58
+  }
+  if (bytes?.length !== expectedLength) {
+    const description =
+      linkType === "transaction" ? "transaction signature" : "address";
+    throw new Error(`'${id}' isn't a valid ${description}.`);
   }
-  return encodeURL(baseUrl, searchParams);
 };
 
-export const getKeypairFromFile = async (filepath?: string) => {
-  const path = await import("path");
-  // Work out correct file name
-  if (!filepath) {
-    filepath = DEFAULT_FILEPATH;
-  }
-  if (filepath[0] === "~") {
-    const home = process.env.HOME || null;
-    if (home) {
-      filepath = path.join(home, filepath.slice(1));
+// Work out the cluster from the search params, using the most specific
+// match, since mainnet doesn't have any search params at all. Cluster
+// params that don't match any cluster, eg '?cluster=custom' without a
+// 'customUrl', aren't mainnet, so return null rather than guessing.
+const getClusterFromSearchParams = (
+  template: ExplorerTemplate,
+  searchParams: URLSearchParams,
+): { cluster: ClusterName; customUrl: string | null } | null => {
+  let bestMatch: {
+    cluster: ClusterName;
+    customUrl: string | null;
+    paramNames: Array<string>;
+  } | null = null;
+  for (const cluster of CLUSTER_NAMES) {
+    const clusterSearchParams = template.clusterSearchParams[cluster];
+    if (!clusterSearchParams) {
+      continue;
     }
+    let customUrl: string | null = null;
+    let isMatch = true;
+    for (const [name, value] of Object.entries(clusterSearchParams)) {
+      const actualValue = searchParams.get(name);
+      if (value === "{customUrl}") {
+        customUrl = actualValue;
+      }
+      if (
+        actualValue === null ||
+        (value !== "{customUrl}" && actualValue !== value)
+      ) {
+        isMatch = false;
+        break;
+      }
+    }
+    const paramNames = Object.keys(clusterSearchParams);
+    const paramCount = paramNames.length;
+    if (!isMatch || (bestMatch && bestMatch.paramNames.length > paramCount)) {
+      continue;
+    }
+    // Solana Explorer and Solscan use the same search params for localnet
+    // and custom clusters, so use the RPC URL to tell them apart
+    if (bestMatch?.paramNames.length === paramCount && customUrl !== null) {
+      let isLocal = false;
+      try {
+        isLocal = isLocalUrl(customUrl);
+      } catch (error) {
+        // Not a URL, so not a local one
+      }
+      if ((cluster === "localnet") !== isLocal) {
+        continue;
+      }
+    }
+    bestMatch = { cluster, customUrl, paramNames };
+  }
+  if (!bestMatch) {
+    return null;
+  }
+  const { cluster, customUrl, paramNames } = bestMatch;
+  const knownParamNames = Object.values(template.clusterSearchParams).flatMap(
+    (clusterSearchParams) => Object.keys(clusterSearchParams),
+  );
+  const hasUnmatchedParams = knownParamNames.some(
+    (name) => searchParams.has(name) && !paramNames.includes(name),
+  );
+  if (hasUnmatchedParams) {
+    return null;
   }
+  return {
+    cluster,
+    customUrl:
+      cluster === "localnet" ? customUrl || DEFAULT_LOCALNET_URL : customUrl,
+  };
+};
 
-  // Get contents of file
-  let fileContents: string;
+// The opposite of getExplorerLink(), for the explorers it supports
+export const parseExplorerLink = (link: string): ParsedExplorerLink => {
+  let url: URL;
   try {
-    const { readFile } = await import("fs/promises");
-    const fileContentsBuffer = await readFile(filepath);
-    fileContents = fileContentsBuffer.toString();
+    url = new URL(link.trim());
   } catch (error) {
-    throw new Error(`Could not read keypair from file at '${filepath}'`);
+    throw new Error(`'${link}' isn't a URL.`);
   }
+  const normalizedUrl = normalizeExplorerUrl(url);
 
-  // Parse contents of file
-  let parsedFileContents: Uint8Array;
-  try {
-    parsedFileContents = Uint8Array.from(JSON.parse(fileContents));
-  } catch (thrownObject) {
-    const error = thrownObject as Error;
-    if (!error.message.includes("Unexpected token")) {
-      throw error;
+  for (const [explorer, template] of Object.entries(EXPLORERS) as Array<
+    [Explorer, ExplorerTemplate]
+  >) {
+    if (
+      template.inspector &&
+      normalizedUrl === normalizeExplorerUrl(new URL(template.inspector))
+    ) {
+      throw new Error(
+        "Inspector links are for transactions that haven't been sent, so don't have a signature.",
+      );
+    }
+
+    for (const linkType of ["transaction", "address", "block"] as const) {
+      const [prefix] = template[linkType].split("{id}");
+      const normalizedPrefix = normalizeExplorerUrl(new URL(prefix)) + "/";
+      if (!normalizedUrl.startsWith(normalizedPrefix)) {
+        continue;
+      }
+      // Ignore tabs after the id, eg '/address/<id>/tokens'
+      const [idSegment] = normalizedUrl.slice(normalizedPrefix.length).split("/");
+      if (!idSegment) {
+        continue;
+      }
+      const id = decodeURIComponent(idSegment);
+
+      const clusterAndCustomUrl = getClusterFromSearchParams(
+        template,
+        url.searchParams,
+      );
+      if (!clusterAndCustomUrl) {
+        throw new Error(
+          `Could not work out the cluster for ${template.name} link '${link}'.`,
+        );
+      }
+      checkExplorerLinkId(linkType, id);
+      return { explorer, linkType, id, ...clusterAndCustomUrl };
     }
-    throw new Error(`Invalid secret key file at '${filepath}'!`);
   }
-  return Keypair.fromSecretKey(parsedFileContents);
+  throw new Error(`'${link}' isn't a link to a supported explorer.`);
 };
 
-export const getKeypairFromEnvironment = (variableName: string) => {
-  const secretKeyString = process.env[variableName];
-  if (!secretKeyString) {
-    throw new Error(`Please set '${variableName}' in environment.`);
+// A keypair that won't reveal its secret key when logged or serialized,
+// and whose secret key can be wiped when it's no longer needed.
+// It's still a Keypair, so can be used anywhere a Keypair can.
+export class RedactedKeypair extends Keypair {
+  #secretKey: Uint8Array;
+  #isDisposed = false;
+
+  constructor(keypair: Keypair) {
+    const secretKey = keypair.secretKey;
+    super({ publicKey: keypair.publicKey.toBytes(), secretKey });
+    this.#secretKey = secretKey;
+    // Hide web3.js' own copy from Object.keys(), spreading, etc.
+    Object.defineProperty(this, "_keypair", { enumerable: false });
+    // Used by console.log() and util.inspect() in node.js
+    Object.defineProperty(this, Symbol.for("nodejs.util.inspect.custom"), {
+      value: () => this.toString(),
+    });
   }
 
-  // Try the shorter base58 format first
-  let decodedSecretKey: Uint8Array;
-  try {
-    decodedSecretKey = base58.decode(secretKeyString);
-    return Keypair.fromSecretKey(decodedSecretKey);
-  } catch (throwObject) {
-    const error = throwObject as Error;
-    if (!error.message.includes("Non-base58 character")) {
+  get secretKey(): Uint8Array {
+    if (this.#isDisposed) {
       throw new Error(
-        `Invalid secret key in environment variable '${variableName}'!`,
+        `The keypair for '${this.publicKey.toBase58()}' has been disposed`,
       );
     }
+    return new Uint8Array(this.#secretKey);
   }
 
-  // Try the longer JSON format
-  try {
-    decodedSecretKey = Uint8Array.from(JSON.parse(secretKeyString));
-  } catch (error) {
-    throw new Error(
-      `Invalid secret key in environment variable '${variableName}'!`,
-    );
+  // Overwrite the secret key with zeros, so it can't be used (or leaked) again
+  dispose() {
+    this.#secretKey.fill(0);
+    this.#isDisposed = true;
+  }
+
+  toString() {
+    return `RedactedKeypair(${this.publicKey.toBase58()})`;
+  }
+
+  toJSON() {
+    return { publicKey: this.publicKey.toBase58() };
   }
-  return Keypair.fromSecretKey(decodedSecretKey);
+}
+
+export const redactKeypair = (keypair: Keypair): RedactedKeypair => {
+  return new RedactedKeypair(keypair);
 };
 
-export const addKeypairToEnvFile = async (
+// All the keypair loaders take a 'redact' option
+export interface RedactOptions {
+  redact?: boolean;
+}
+
+const redactIfRequired = (
   keypair: Keypair,
-  variableName: string,
-  envFileName?: string,
-) => {
-  const { appendFile } = await import("fs/promises");
-  if (!envFileName) {
-    envFileName = ".env";
+  options?: RedactOptions,
+): Keypair => {
+  return options?.redact ? redactKeypair(keypair) : keypair;
+};
+
+export type SecretKeyFormat = "json" | "base58" | "hex" | "base64" | "bytes";
+
+export class SecretKeyParseError extends Error {
+  // null if the format couldn't be detected at all
+  format: SecretKeyFormat | null;
+  length: number | null;
+  expectedLengths: Array<number>;
+
+  constructor(
+    message: string,
+    format: SecretKeyFormat | null = null,
+    length: number | null = null,
+  ) {
+    super(message);
+    this.name = "SecretKeyParseError";
+    this.format = format;
+    this.length = length;
+    this.expectedLengths = SECRET_KEY_LENGTHS;
   }
-  const existingSecretKey = process.env[variableName];
-  if (existingSecretKey) {
-    throw new Error(`'${variableName}' already exists in env file.`);
+}
+
+const decodeHex = (hex: string): Uint8Array => {
+  const bytes = new Uint8Array(hex.length / 2);
+  for (let index = 0; index < bytes.length; index++) {
+    bytes[index] = parseInt(hex.slice(index * 2, index * 2 + 2), 16);
   }
-  const secretKeyString = keypairToSecretKeyJSON(keypair);
-  await appendFile(
-    envFileName,
-    `\n# Solana Address: ${keypair.publicKey.toBase58()}\n${variableName}=${secretKeyString}`,
-  );
+  return bytes;
 };
 
-export interface InitializeKeypairOptions {
-  envFileName?: string;
-  envVariableName?: string;
-  airdropAmount?: number | null;
-  minimumBalance?: number;
-  keypairPath?: string;
-}
+const encodeBase64 = (bytes: Uint8Array): string => {
+  // btoa() works in both node.js and browsers
+  return btoa(String.fromCharCode(...bytes));
+};
 
-export const initializeKeypair = async (
-  connection: Connection,
-  options?: InitializeKeypairOptions,
-): Promise<Keypair> => {
-  let {
-    keypairPath,
-    envFileName,
-    envVariableName = DEFAULT_ENV_KEYPAIR_VARIABLE_NAME,
-    airdropAmount = DEFAULT_AIRDROP_AMOUNT,
-    minimumBalance = DEFAULT_MINIMUM_BALANCE,
-  } = options || {};
+const decodeBase64 = (base64: string): Uint8Array => {
+  // atob() works in both node.js and browsers
+  return Uint8Array.from(atob(base64), (character) => character.charCodeAt(0));
+};
 
-  let keypair: Keypair;
+// Work out every format a secret key string could be in
+const decodeSecretKeyString = (
+  secretKeyString: string,
+): Array<{ format: SecretKeyFormat; bytes: Uint8Array }> => {
+  // A JSON array is unambiguous, so there's no need to try anything else
+  if (secretKeyString.startsWith("[")) {
+    let numbers: unknown;
+    try {
+      numbers = JSON.parse(secretKeyString);
+    } catch (error) {
+      throw new SecretKeyParseError(
+        "Secret key looks like a JSON array, but isn't valid JSON",
+        "json",
+      );
+    }
+    if (
+      !Array.isArray(numbers) ||
+      !numbers.every(
+        (number) => Number.isInteger(number) && number >= 0 && number <= 255,
+      )
+    ) {
+      throw new SecretKeyParseError(
+        "Secret key looks like a JSON array, but it should only contain numbers from 0 to 255",
+        "json",
+      );
+    }
+    return [{ format: "json", bytes: Uint8Array.from(numbers) }];
+  }
 
-  if (keypairPath) {
-    keypair = await getKeypairFromFile(keypairPath);
-  } else if (process.env[envVariableName]) {
-    keypair = getKeypairFromEnvironment(envVariableName);
-  } else {
-    keypair = Keypair.generate();
-    await addKeypairToEnvFile(keypair, envVariableName, envFileName);
+  const decoded: Array<{ format: SecretKeyFormat; bytes: Uint8Array }> = [];
+  const hex = secretKeyString.replace(/^0x/, "");
+  if (/^[0-9a-fA-F]+$/.test(hex) && hex.length % 2 === 0) {
+    decoded.push({ format: "hex", bytes: decodeHex(hex) });
+  }
+  try {
+    decoded.push({ format: "base58", bytes: base58.decode(secretKeyString) });
+  } catch (error) {
+    // Not base58
   }
+  // We require padding, otherwise many base58 strings would also be valid base64
+  if (
+    /^[A-Za-z0-9+/]+={0,2}$/.test(secretKeyString) &&
+    secretKeyString.length % 4 === 0
+  ) {
+    decoded.push({ format: "base64", bytes: decodeBase64(secretKeyString) });
+  }
+  return decoded;
+};
 
-  if (airdropAmount) {
-    await airdropIfRequired(
-      connection,
-      keypair.publicKey,
-      airdropAmount,
-      minimumBalance,
+// Detect the format and decode a secret key, without making a Keypair
+// (which keeps copies of the secret key), so callers can wipe the bytes
+const decodeSecretKey = (
+  input: string | Uint8Array | Array<number>,
+): { format: SecretKeyFormat; bytes: Uint8Array } => {
+  let format: SecretKeyFormat;
+  let bytes: Uint8Array;
+
+  if (typeof input === "string") {
+    const decoded = decodeSecretKeyString(input.trim());
+    if (!decoded.length) {
+      throw new SecretKeyParseError(
+        "Could not detect the secret key format, expected a JSON array of numbers, base58, hex or base64",
+      );
+    }
+    const validLengths = decoded.filter(({ bytes }) =>
+      SECRET_KEY_LENGTHS.includes(bytes.length),
     );
+    if (!validLengths.length) {
+      // Report the most likely format, ie, the first one we tried
+      const { format, bytes } = decoded[0];
+      throw new SecretKeyParseError(
+        `Secret key looks like ${format} but is ${bytes.length} bytes long, expected ${SECRET_KEY_LENGTHS.join(" or ")} bytes`,
+        format,
+        bytes.length,
+      );
+    }
+    if (validLengths.length > 1) {
+      throw new SecretKeyParseError(
+        `Secret key is ambiguous, it could be ${validLengths.map(({ format }) => format).join(" or ")}`,
+      );
+    }
+    ({ format, bytes } = validLengths[0]);
+  } else {
+    format = "bytes";
+    bytes = Uint8Array.from(input);
+    if (!SECRET_KEY_LENGTHS.includes(bytes.length)) {
+      throw new SecretKeyParseError(
+        `Secret key is ${bytes.length} bytes long, expected ${SECRET_KEY_LENGTHS.join(" or ")} bytes`,
+        format,
+        bytes.length,
+      );
+    }
+  }
+
+  if (bytes.length === 64) {
+    const publicKey = ed25519.getPublicKey(bytes.subarray(0, 32));
+    const isMatchingPublicKey = publicKey.every(
+      (byte, index) => byte === bytes[32 + index],
+    );
+    if (!isMatchingPublicKey) {
+      throw new SecretKeyParseError(
+        `Secret key (${format}) is 64 bytes long, but the public key doesn't match the seed`,
+        format,
+        bytes.length,
+      );
+    }
+  }
+  return { format, bytes };
+};
+
+// Make a keypair from a secret key in any of the formats commonly used by Solana tools:
+// a JSON array of numbers (like the Solana CLI), base58 (like wallet apps), hex or base64.
+// The secret key can either be 64 bytes (seed and public key) or a 32 byte seed.
+export const parseSecretKey = (
+  input: string | Uint8Array | Array<number>,
+): Keypair => {
+  const { bytes } = decodeSecretKey(input);
+  return Keypair.fromSeed(bytes.slice(0, 32));
+};
+
+// Expand '~' to the user's home directory, like a shell would
+const expandHomeDirectory = async (filepath: string): Promise<string> => {
+  const path = await import("path");
+  if (filepath[0] === "~") {
+    const home = process.env.HOME || null;
+    if (home) {
+      return path.join(home, filepath.slice(1));
+    }
+  }
+  return filepath;
+};
+
+export interface EncryptedKeypairFile {
+  version: 1;
+  publicKey: string;
+  kdf: "scrypt";
+  kdfParams: {
+    n: number;
+    r: number;
+    p: number;
+    salt: string;
+  };
+  cipher: "aes-256-gcm";
+  cipherParams: {
+    iv: string;
+    authTag: string;
+  };
+  ciphertext: string;
+}
+
+const isEncryptedKeypairFile = (
+  contents: unknown,
+): contents is EncryptedKeypairFile => {
+  return (
+    typeof contents === "object" &&
+    contents !== null &&
+    !Array.isArray(contents) &&
+    "version" in contents &&
+    "ciphertext" in contents
+  );
+};
+
+const deriveKeyWithScrypt = async (
+  password: string,
+  salt: Buffer,
+  { n, r, p }: { n: number; r: number; p: number },
+): Promise<Buffer> => {
+  const { scrypt } = await import("crypto");
+  return new Promise((resolve, reject) => {
+    // Our scrypt parameters need more memory than node's 32MB default
+    const maxmem = 256 * n * r;
+    scrypt(password, salt, 32, { N: n, r, p, maxmem }, (error, key) =>
+      error ? reject(error) : resolve(key),
+    );
+  });
+};
+
+// No more than the parameters we encrypt with, and n must be a power of 2
+const isAllowedScryptParams = ({
+  n,
+  r,
+  p,
+}: {
+  n: number;
+  r: number;
+  p: number;
+}) =>
+  Number.isInteger(n) &&
+  Number.isInteger(r) &&
+  Number.isInteger(p) &&
+  n > 1 &&
+  n <= SCRYPT_PARAMS.n &&
+  (n & (n - 1)) === 0 &&
+  r >= 1 &&
+  r <= SCRYPT_PARAMS.r &&
+  p >= 1 &&
+  p <= SCRYPT_PARAMS.p;
+
+// The public key isn't secret, but we authenticate it so it can't be swapped
+const getEncryptedKeypairAdditionalData = (publicKey: string) =>
+  new TextEncoder().encode(publicKey);
+
+const makeDecryptKeypairError = (publicKey: string) =>
+  new Error(
+    `Could not decrypt keypair for '${publicKey}', is the password correct?`,
+  );
+
+export const encryptKeypair = async (
+  keypair: Keypair,
+  password: string,
+): Promise<EncryptedKeypairFile> => {
+  const { createCipheriv, randomBytes } = await import("crypto");
+
+  const salt = randomBytes(32);
+  const iv = randomBytes(12);
+  const key = await deriveKeyWithScrypt(password, salt, SCRYPT_PARAMS);
+
+  const publicKey = keypair.publicKey.toBase58();
+  const cipher = createCipheriv("aes-256-gcm", key, iv);
+  cipher.setAAD(getEncryptedKeypairAdditionalData(publicKey));
+  const ciphertext = Buffer.concat([
+    cipher.update(keypair.secretKey),
+    cipher.final(),
+  ]);
+
+  return {
+    version: ENCRYPTED_KEYPAIR_FILE_VERSION,
+    publicKey,
+    kdf: "scrypt",
+    kdfParams: { ...SCRYPT_PARAMS, salt: salt.toString("base64") },
+    cipher: "aes-256-gcm",
+    cipherParams: {
+      iv: iv.toString("base64"),
+      authTag: cipher.getAuthTag().toString("base64"),
+    },
+    ciphertext: ciphertext.toString("base64"),
+  };
+};
+
+export const decryptKeypair = async (
+  encryptedKeypairFile: EncryptedKeypairFile,
+  password: string,
+): Promise<Keypair> => {
+  const { createDecipheriv } = await import("crypto");
+
+  const { version, publicKey, kdf, kdfParams, cipher, cipherParams } =
+    encryptedKeypairFile;
+  if (
+    version !== ENCRYPTED_KEYPAIR_FILE_VERSION ||
+    kdf !== "scrypt" ||
+    cipher !== "aes-256-gcm"
+  ) {
+    throw new Error(
+      `Unsupported encrypted keypair format (version ${version}, ${kdf}, ${cipher})`,
+    );
+  }
+
+  // The parameters come from the file, so anyone who can edit it could
+  // otherwise make us allocate gigabytes of memory, or hang, deriving the key
+  const { n, r, p } = kdfParams;
+  if (!isAllowedScryptParams(kdfParams)) {
+    throw new Error(
+      `Unsupported scrypt parameters (n ${n}, r ${r}, p ${p}), expected at most n ${SCRYPT_PARAMS.n}, r ${SCRYPT_PARAMS.r}, p ${SCRYPT_PARAMS.p}`,
+    );
+  }
+
+  const key = await deriveKeyWithScrypt(
+    password,
+    Buffer.from(kdfParams.salt, "base64"),
+    kdfParams,
+  );
+
+  let secretKey: Buffer;
+  try {
+    const decipher = createDecipheriv(
+      "aes-256-gcm",
+      key,
+      Buffer.from(cipherParams.iv, "base64"),
+    );
+    decipher.setAAD(getEncryptedKeypairAdditionalData(publicKey));
+    decipher.setAuthTag(Buffer.from(cipherParams.authTag, "base64"));
+    secretKey = Buffer.concat([
+      decipher.update(Buffer.from(encryptedKeypairFile.ciphertext, "base64")),
+      decipher.final(),
+    ]);
+  } catch (error) {
+    throw makeDecryptKeypairError(publicKey);
+  }
+  return Keypair.fromSecretKey(secretKey);
+};
+
+// Write to a temporary file first, then move it into place, so there's
+// never a half-written keypair file if something goes wrong.
+const writeSecretFile = async (
+  filepath: string,
+  contents: string,
+  overwrite: boolean,
+) => {
+  const { link, mkdir, open, rename, unlink, writeFile } =
+    await import("fs/promises");
+  const path = await import("path");
+  const { randomBytes } = await import("crypto");
+
+  filepath = await expandHomeDirectory(filepath);
+  const directory = path.dirname(filepath);
+  await mkdir(directory, { recursive: true });
+
+  const temporaryFilepath = path.join(
+    directory,
+    `.${path.basename(filepath)}.${randomBytes(6).toString("hex")}.tmp`,
+  );
+  // Only the current user can read or write secret keys
+  await writeFile(temporaryFilepath, contents, { mode: 0o600, flag: "wx" });
+
+  try {
+    if (overwrite) {
+      await rename(temporaryFilepath, filepath);
+    } else {
+      try {
+        // Unlike rename(), link() fails if the file already exists
+        await link(temporaryFilepath, filepath);
+      } catch (thrownObject) {
+        const error = thrownObject as NodeJS.ErrnoException;
+        if (!HARD_LINK_UNSUPPORTED_ERROR_CODES.includes(error.code || "")) {
+          throw error;
+        }
+        // The 'wx' flag also fails if the file already exists, but the file
+        // is written in place, so it's only a fallback
+        const fileHandle = await open(filepath, "wx", 0o600);
+        try {
+          await fileHandle.writeFile(contents);
+          await fileHandle.sync();
+        } catch (writeError) {
+          // Don't leave a half-written keypair file behind
+          await fileHandle.close();
+          await unlink(filepath).catch(() => {});
+          throw writeError;
+        }
+        await fileHandle.close();
+      }
+      await unlink(temporaryFilepath);
+    }
+  } catch (thrownObject) {
+    await unlink(temporaryFilepath).catch(() => {});
+    const error = thrownObject as NodeJS.ErrnoException;
+    if (error.code === "EEXIST") {
+      throw new Error(
+        `File at '${filepath}' already exists, set 'overwrite' to replace it.`,
+      );
+    }
+    throw error;
+  }
+};
+
+export interface SaveKeypairToFileOptions {
+  overwrite?: boolean;
+}
+
+export const saveEncryptedKeypair = async (
+  keypair: Keypair,
+  filepath: string,
+  password: string,
+  options?: SaveKeypairToFileOptions,
+) => {
+  const encryptedKeypairFile = await encryptKeypair(keypair, password);
+  await writeSecretFile(
+    filepath,
+    JSON.stringify(encryptedKeypairFile, null, 2),
+    options?.overwrite || false,
+  );
+};
+
+// Save a keypair in the same format as 'solana-keygen new'
+export const saveKeypairToFile = async (
+  keypair: Keypair,
+  filepath: string,
+  options?: SaveKeypairToFileOptions,
+) => {
+  await writeSecretFile(
+    filepath,
+    keypairToSecretKeyJSON(keypair),
+    options?.overwrite || false,
+  );
+};
+
+export interface SolanaCliConfig {
+  jsonRpcUrl: string;
+  websocketUrl: string;
+  keypairPath: string;
+  commitment: Commitment;
+}
+
+// The Solana CLI only writes simple 'key: value' lines (and some nested
+// address labels we don't need), so we don't need a full YAML parser
+const parseCliConfigFile = (contents: string): Record<string, string> => {
+  const values: Record<string, string> = {};
+  for (const line of contents.split(/\r?\n/)) {
+    const match = /^(?<key>[a-z_]+):[ \t]*(?<value>.*?)[ \t]*$/.exec(line);
+    if (!match?.groups) {
+      continue;
+    }
+    let value = match.groups.value;
+    if (/^(["']).*\1$/.test(value)) {
+      value = value.slice(1, -1);
+    }
+    values[match.groups.key] = value;
+  }
+  return values;
+};
+
+// Like the Solana CLI, use the RPC URL (with the port after the RPC port)
+// if no websocket URL is set
+const getWebsocketUrl = (jsonRpcUrl: string): string => {
+  const url = new URL(jsonRpcUrl);
+  url.protocol = url.protocol === "https:" ? "wss:" : "ws:";
+  if (url.port) {
+    url.port = String(Number(url.port) + 1);
+  }
+  return url.toString();
+};
+
+// Get the settings from 'solana config set'
+export const getSolanaCliConfig = async (
+  configFilepath?: string,
+): Promise<SolanaCliConfig> => {
+  const isDefaultConfigFile = !configFilepath && !process.env.SOLANA_CONFIG;
+  const filepath = await expandHomeDirectory(
+    configFilepath || process.env.SOLANA_CONFIG || DEFAULT_CLI_CONFIG_FILEPATH,
+  );
+
+  let values: Record<string, string> = {};
+  try {
+    const { readFile } = await import("fs/promises");
+    values = parseCliConfigFile(await readFile(filepath, "utf8"));
+  } catch (error) {
+    // The Solana CLI uses its defaults if there's no config file yet,
+    // but if a config file was specified, it should be there
+    if (!isDefaultConfigFile) {
+      throw new Error(`Could not read Solana CLI config from '${filepath}'`);
+    }
+  }
+
+  const jsonRpcUrl = values.json_rpc_url || DEFAULT_CLI_RPC_URL;
+  return {
+    jsonRpcUrl,
+    websocketUrl: values.websocket_url || getWebsocketUrl(jsonRpcUrl),
+    keypairPath: values.keypair_path || DEFAULT_FILEPATH,
+    commitment: (values.commitment as Commitment) || DEFAULT_CLI_COMMITMENT,
+  };
+};
+
+export type KeypairValidationProblem =
+  | "layout"
+  | "file-permissions"
+  | "unexpected-public-key";
+
+export class KeypairValidationError extends Error {
+  problem: KeypairValidationProblem;
+  filepath: string;
+
+  constructor(
+    message: string,
+    problem: KeypairValidationProblem,
+    filepath: string,
+  ) {
+    super(message);
+    this.name = "KeypairValidationError";
+    this.problem = problem;
+    this.filepath = filepath;
+  }
+}
+
+export interface GetKeypairFromFileOptions extends RedactOptions {
+  password?: string;
+  // Throw rather than warn about files that aren't in the solana-keygen
+  // format, or that other users can read
+  strict?: boolean;
+  // Eg, the wallet from Anchor.toml, to check we loaded the right keypair
+  expectedPublicKey?: PublicKey | string;
+}
+
+// Checks that don't stop a keypair from loading. Loose file permissions
+// are only a warning unless 'strict' is set, and since we read other
+// secret key formats too, the layout is only checked when 'strict' is set.
+const checkKeypairFile = async (
+  filepath: string,
+  fileContents: string,
+  isEncrypted: boolean,
+  strict: boolean,
+) => {
+  const problems: Array<KeypairValidationError> = [];
+
+  if (strict && !isEncrypted && !isSolanaKeygenSecretKey(fileContents)) {
+    problems.push(
+      new KeypairValidationError(
+        `Keypair file at '${filepath}' isn't a JSON array of 64 numbers, like 'solana-keygen' makes.`,
+        "layout",
+        filepath,
+      ),
+    );
+  }
+
+  // Windows doesn't have POSIX file permissions
+  if (process.platform !== "win32") {
+    const { stat } = await import("fs/promises");
+    const { mode } = await stat(filepath);
+    if (mode & 0o077) {
+      problems.push(
+        new KeypairValidationError(
+          `Keypair file at '${filepath}' can be read by other users, run 'chmod 600 ${filepath}' to fix this.`,
+          "file-permissions",
+          filepath,
+        ),
+      );
+    }
+  }
+
+  if (strict && problems.length) {
+    throw problems[0];
+  }
+  for (const problem of problems) {
+    log(`Warning: ${problem.message}`);
+  }
+};
+
+const isSolanaKeygenSecretKey = (fileContents: string) => {
+  try {
+    const parsed = JSON.parse(fileContents);
+    return (
+      Array.isArray(parsed) &&
+      parsed.length === 64 &&
+      parsed.every(
+        (byte) => Number.isInteger(byte) && byte >= 0 && byte <= 255,
+      )
+    );
+  } catch (error) {
+    return false;
+  }
+};
+
+const checkExpectedPublicKey = (
+  keypair: Keypair,
+  filepath: string,
+  expectedPublicKey?: PublicKey | string,
+) => {
+  if (!expectedPublicKey) {
+    return;
+  }
+  const expectedAddress = new PublicKey(expectedPublicKey).toBase58();
+  const address = keypair.publicKey.toBase58();
+  if (address !== expectedAddress) {
+    throw new KeypairValidationError(
+      `Keypair file at '${filepath}' is for '${address}', expected '${expectedAddress}'.`,
+      "unexpected-public-key",
+      filepath,
+    );
+  }
+};
+
+export const getKeypairFromFile = async (
+  filepath?: string,
+  options?: GetKeypairFromFileOptions,
+) => {
+  // Work out correct file name
+  if (!filepath) {
+    filepath = (await getSolanaCliConfig()).keypairPath;
+  }
+  filepath = await expandHomeDirectory(filepath);
+
+  // Get contents of file
+  let fileContents: string;
+  try {
+    const { readFile } = await import("fs/promises");
+    const fileContentsBuffer = await readFile(filepath);
+    fileContents = fileContentsBuffer.toString();
+  } catch (error) {
+    throw new Error(`Could not read keypair from file at '${filepath}'`);
+  }
+
+  // Encrypted keypair files are JSON objects, see saveEncryptedKeypair()
+  let parsedFileContents: unknown = null;
+  try {
+    parsedFileContents = JSON.parse(fileContents);
+  } catch (error) {
+    // Not JSON, but might be another secret key format
+  }
+
+  let keypair: Keypair;
+  if (isEncryptedKeypairFile(parsedFileContents)) {
+    const password = options?.password;
+    if (!password) {
+      throw new Error(
+        `Keypair file at '${filepath}' is encrypted, please provide a password.`,
+      );
+    }
+    keypair = await decryptKeypair(parsedFileContents, password);
+  } else {
+    try {
+      // Also checks the public key matches the seed
+      keypair = parseSecretKey(fileContents);
+    } catch (error) {
+      throw new Error(`Invalid secret key file at '${filepath}'!`, {
+        cause: error,
+      });
+    }
+  }
+  await checkKeypairFile(
+    filepath,
+    fileContents,
+    isEncryptedKeypairFile(parsedFileContents),
+    options?.strict || false,
+  );
+  checkExpectedPublicKey(keypair, filepath, options?.expectedPublicKey);
+  return redactIfRequired(keypair, options);
+};
+
+export const getConnectionAndKeypairFromCliConfig = async (
+  configFilepath?: string,
+  options?: RedactOptions,
+) => {
+  const config = await getSolanaCliConfig(configFilepath);
+  const connection = new Connection(config.jsonRpcUrl, {
+    commitment: config.commitment,
+    wsEndpoint: config.websocketUrl,
+  });
+  const keypair = await getKeypairFromFile(config.keypairPath, options);
+  return { connection, keypair, config };
+};
+
+export const getKeypairFromEnvironment = (
+  variableName: string,
+  options?: RedactOptions,
+) => {
+  const secretKeyString = process.env[variableName];
+  if (!secretKeyString) {
+    throw new Error(`Please set '${variableName}' in environment.`);
+  }
+
+  let keypair: Keypair;
+  try {
+    keypair = parseSecretKey(secretKeyString);
+  } catch (error) {
+    throw new Error(
+      `Invalid secret key in environment variable '${variableName}'!`,
+      { cause: error },
+    );
+  }
+  return redactIfRequired(keypair, options);
+};
+
+// Derive a private key from a BIP39 seed using SLIP-0010
+// (the ed25519 variant of BIP32, used by Solana wallets)
+const deriveEd25519PrivateKey = (
+  seed: Uint8Array,
+  derivationPath: string,
+): Uint8Array => {
+  const segments = derivationPath.split("/");
+  if (segments[0] !== "m") {
+    throw new Error(
+      `Invalid derivation path '${derivationPath}', it should start with 'm/'`,
+    );
+  }
+
+  let digest = hmac(sha512, "ed25519 seed", seed);
+  let privateKey = digest.slice(0, 32);
+  let chainCode = digest.slice(32);
+
+  for (const segment of segments.slice(1)) {
+    const match = /^(?<index>[0-9]+)'$/.exec(segment);
+    const index = Number(match?.groups?.index);
+    if (!match || index >= HARDENED_OFFSET) {
+      throw new Error(
+        `Invalid derivation path '${derivationPath}', every segment must be hardened (like 501')`,
+      );
+    }
+    const data = new Uint8Array(37);
+    data.set(privateKey, 1);
+    new DataView(data.buffer).setUint32(33, index + HARDENED_OFFSET);
+
+    digest = hmac(sha512, chainCode, data);
+    privateKey = digest.slice(0, 32);
+    chainCode = digest.slice(32);
+  }
+  return privateKey;
+};
+
+export interface GetKeypairFromMnemonicOptions extends RedactOptions {
+  passphrase?: string;
+  derivationPath?: string;
+}
+
+// The last bits of a mnemonic are a checksum of the rest, see
+// https://github.com/bitcoin/bips/blob/master/bip-0039.mediawiki#generating-the-mnemonic
+const isValidMnemonicChecksum = (words: Array<string>): boolean => {
+  const bits = words
+    .map((word) =>
+      BIP39_ENGLISH_WORDLIST.indexOf(word).toString(2).padStart(11, "0"),
+    )
+    .join("");
+  const checksumLength = bits.length / 33;
+  const entropyBits = bits.slice(0, -checksumLength);
+  const entropy = new Uint8Array(entropyBits.length / 8);
+  for (let index = 0; index < entropy.length; index++) {
+    entropy[index] = parseInt(entropyBits.slice(index * 8, index * 8 + 8), 2);
+  }
+  const expectedChecksum = sha256(entropy)[0]
+    .toString(2)
+    .padStart(8, "0")
+    .slice(0, checksumLength);
+  return bits.slice(-checksumLength) === expectedChecksum;
+};
+
+export const getKeypairFromMnemonic = (
+  mnemonic: string,
+  options?: GetKeypairFromMnemonicOptions,
+): Keypair => {
+  const { passphrase = "", derivationPath } = options || {};
+
+  const words = mnemonic.normalize("NFKD").trim().toLowerCase().split(/\s+/);
+  if (!VALID_MNEMONIC_WORD_COUNTS.includes(words.length)) {
+    throw new Error(
+      `Invalid mnemonic, expected ${VALID_MNEMONIC_WORD_COUNTS.join(", ")} words but got ${words.length}`,
+    );
+  }
+  // A typo would otherwise give a different, but still valid, wallet
+  const unknownWord = words.find(
+    (word) => !BIP39_ENGLISH_WORDLIST.includes(word),
+  );
+  if (unknownWord) {
+    throw new Error(
+      `Invalid mnemonic, '${unknownWord}' isn't in the BIP39 English wordlist`,
+    );
+  }
+  if (!isValidMnemonicChecksum(words)) {
+    throw new Error(
+      `Invalid mnemonic, the checksum doesn't match, check the words are correct and in the right order`,
+    );
+  }
+
+  // See https://github.com/bitcoin/bips/blob/master/bip-0039.mediawiki#from-mnemonic-to-seed
+  const seed = pbkdf2(
+    sha512,
+    words.join(" "),
+    `mnemonic${passphrase.normalize("NFKD")}`,
+    { c: 2048, dkLen: 64 },
+  );
+
+  // 'solana-keygen new' uses the first 32 bytes of the seed
+  // directly, unless a derivation path is given
+  const keypair = derivationPath
+    ? Keypair.fromSeed(deriveEd25519PrivateKey(seed, derivationPath))
+    : Keypair.fromSeed(seed.slice(0, 32));
+  return redactIfRequired(keypair, options);
+};
+
+// Matches a variable in an env file, in the same way as dotenv.
+// Values can be quoted (and quoted values can span multiple lines) and
+// have comments after them.
+// See https://github.com/motdotla/dotenv/blob/master/lib/main.js
+const ENV_FILE_VARIABLE =
+  /^(?<prefix>[ \t]*(?:export[ \t]+)?(?<name>[\w.-]+)[ \t]*=[ \t]*)(?<value>'(?:\\'|[^'])*'|"(?:\\"|[^"])*"|`(?:\\`|[^`])*`|[^#\r\n]*?)[ \t]*(?:#[^\r\n]*)?$/gmd;
+
+export interface EnvFileVariable {
+  name: string;
+  value: string;
+  // Where the whole line, and just the value, are in the file
+  start: number;
+  end: number;
+  valueStart: number;
+  valueEnd: number;
+}
+
+export const parseEnvFile = (contents: string): Array<EnvFileVariable> => {
+  return Array.from(contents.matchAll(ENV_FILE_VARIABLE), (match) => {
+    const groups = match.groups as { name: string; value: string };
+    const [valueStart, valueEnd] = match.indices?.groups?.value as [
+      number,
+      number,
+    ];
+    let value = groups.value;
+    const quote = value[0];
+    if (["'", '"', "`"].includes(quote) && value.endsWith(quote)) {
+      value = value.slice(1, -1);
+      if (quote === '"') {
+        value = value.replace(/\\n/g, "\n").replace(/\\r/g, "\r");
+      }
+    }
+    return {
+      name: groups.name,
+      value,
+      start: match.index as number,
+      end: (match.index as number) + match[0].length,
+      valueStart,
+      valueEnd,
+    };
+  });
+};
+
+// Quote values only when they need it, so simple values look the
+// same as if someone had typed them by hand
+const formatEnvFileValue = (value: string): string => {
+  if (/^[^\s#'"`\\]*$/.test(value)) {
+    return value;
+  }
+  // Single quoted values are used as is, including new lines
+  if (!value.includes("'")) {
+    return `'${value}'`;
+  }
+  if (!value.includes('"') && !value.includes("\\")) {
+    return `"${value.replace(/\n/g, "\\n").replace(/\r/g, "\\r")}"`;
+  }
+  return `\`${value}\``;
+};
+
+const readEnvFile = async (envFileName: string): Promise<string | null> => {
+  const { readFile } = await import("fs/promises");
+  try {
+    return await readFile(envFileName, "utf8");
+  } catch (thrownObject) {
+    const error = thrownObject as NodeJS.ErrnoException;
+    if (error.code === "ENOENT") {
+      return null;
+    }
+    throw error;
+  }
+};
+
+export const getEnvFileVariable = async (
+  envFileName: string,
+  variableName: string,
+): Promise<string | null> => {
+  const contents = (await readEnvFile(envFileName)) || "";
+  // Like dotenv, the first value wins if a variable is set more than once
+  const variable = parseEnvFile(contents).find(
+    ({ name }) => name === variableName,
+  );
+  return variable ? variable.value : null;
+};
+
+export const addEnvFileVariable = async (
+  envFileName: string,
+  variableName: string,
+  value: string,
+  comment?: string,
+) => {
+  const { writeFile } = await import("fs/promises");
+  const contents = (await readEnvFile(envFileName)) || "";
+  if (parseEnvFile(contents).some(({ name }) => name === variableName)) {
+    throw new Error(`'${variableName}' already exists in env file.`);
+  }
+  const separator = contents && !contents.endsWith("\n") ? "\n" : "";
+  const commentLine = comment ? `# ${comment}\n` : "";
+  await writeFile(
+    envFileName,
+    `${contents}${separator}${commentLine}${variableName}=${formatEnvFileValue(value)}\n`,
+  );
+};
+
+export const replaceEnvFileVariable = async (
+  envFileName: string,
+  variableName: string,
+  value: string,
+) => {
+  const { writeFile } = await import("fs/promises");
+  const contents = (await readEnvFile(envFileName)) || "";
+  const variables = parseEnvFile(contents).filter(
+    ({ name }) => name === variableName,
+  );
+  if (!variables.length) {
+    throw new Error(`'${variableName}' does not exist in env file.`);
+  }
+  // Work backwards so the earlier positions stay the same
+  let newContents = contents;
+  for (const { valueStart, valueEnd } of variables.reverse()) {
+    newContents =
+      newContents.slice(0, valueStart) +
+      formatEnvFileValue(value) +
+      newContents.slice(valueEnd);
+  }
+  await writeFile(envFileName, newContents);
+};
+
+// Returns whether the variable was in the env file
+export const removeEnvFileVariable = async (
+  envFileName: string,
+  variableName: string,
+): Promise<boolean> => {
+  const { writeFile } = await import("fs/promises");
+  const contents = await readEnvFile(envFileName);
+  if (contents === null) {
+    return false;
+  }
+  const variables = parseEnvFile(contents).filter(
+    ({ name }) => name === variableName,
+  );
+  if (!variables.length) {
+    return false;
+  }
+  let newContents = contents;
+  for (const { start, end } of variables.reverse()) {
+    // Remove the line ending too
+    const lineEnding = /^\r?\n/.exec(newContents.slice(end));
+    newContents =
+      newContents.slice(0, start) +
+      newContents.slice(end + (lineEnding ? lineEnding[0].length : 0));
+  }
+  await writeFile(envFileName, newContents);
+  return true;
+};
+
+// Keep a variable in the env file, but as a comment, so it's no longer used
+const commentOutEnvFileVariable = async (
+  envFileName: string,
+  variableName: string,
+  comment: string,
+): Promise<boolean> => {
+  const { writeFile } = await import("fs/promises");
+  const contents = await readEnvFile(envFileName);
+  if (contents === null) {
+    return false;
+  }
+  const variables = parseEnvFile(contents).filter(
+    ({ name }) => name === variableName,
+  );
+  if (!variables.length) {
+    return false;
+  }
+  let newContents = contents;
+  for (const { start, end } of variables.reverse()) {
+    // Quoted values can span multiple lines
+    const commentedOutLines = newContents
+      .slice(start, end)
+      .replace(/^/gm, "# ");
+    newContents =
+      newContents.slice(0, start) +
+      `# ${comment}\n${commentedOutLines}` +
+      newContents.slice(end);
+  }
+  await writeFile(envFileName, newContents);
+  return true;
+};
+
+export const addKeypairToEnvFile = async (
+  keypair: Keypair,
+  variableName: string,
+  envFileName?: string,
+) => {
+  if (!envFileName) {
+    envFileName = DEFAULT_ENV_FILE_NAME;
+  }
+  await addEnvFileVariable(
+    envFileName,
+    variableName,
+    keypairToSecretKeyJSON(keypair),
+    `Solana Address: ${keypair.publicKey.toBase58()}`,
+  );
+};
+
+// Where keypairs are kept in the browser. Any object with these methods
+// will work, eg, to use a different storage API.
+export interface KeypairStorage {
+  getItem(key: string): Promise<string | null>;
+  setItem(key: string, value: string): Promise<void>;
+  removeItem(key: string): Promise<void>;
+}
+
+export const makeLocalStorageKeypairStorage = (
+  storage: Storage = globalThis.localStorage,
+): KeypairStorage => {
+  return {
+    getItem: async (key) => storage.getItem(key),
+    setItem: async (key, value) => storage.setItem(key, value),
+    removeItem: async (key) => storage.removeItem(key),
+  };
+};
+
+export const makeIndexedDbKeypairStorage = (
+  databaseName: string = DEFAULT_INDEXED_DB_NAME,
+): KeypairStorage => {
+  const storeName = "keypairs";
+
+  const openDatabase = () =>
+    new Promise<IDBDatabase>((resolve, reject) => {
+      const request = globalThis.indexedDB.open(databaseName, 1);
+      request.onupgradeneeded = () => {
+        request.result.createObjectStore(storeName);
+      };
+      request.onsuccess = () => resolve(request.result);
+      request.onerror = () => reject(request.error);
+    });
+
+  // Wait for the whole transaction, so writes are saved before we return
+  const runTransaction = async <T>(
+    mode: IDBTransactionMode,
+    makeRequest: (store: IDBObjectStore) => IDBRequest<T>,
+  ): Promise<T> => {
+    const database = await openDatabase();
+    try {
+      return await new Promise<T>((resolve, reject) => {
+        const transaction = database.transaction(storeName, mode);
+        const request = makeRequest(transaction.objectStore(storeName));
+        transaction.oncomplete = () => resolve(request.result);
+        transaction.onerror = () => reject(transaction.error);
+        transaction.onabort = () => reject(transaction.error);
+      });
+    } finally {
+      database.close();
+    }
+  };
+
+  return {
+    getItem: async (key) =>
+      (await runTransaction("readonly", (store) => store.get(key))) ?? null,
+    setItem: async (key, value) => {
+      await runTransaction("readwrite", (store) => store.put(value, key));
+    },
+    removeItem: async (key) => {
+      await runTransaction("readwrite", (store) => store.delete(key));
+    },
+  };
+};
+
+// IndexedDB is preferred since, unlike localStorage, it's not
+// readable by every script on the page via a simple property lookup
+const getDefaultKeypairStorage = (): KeypairStorage => {
+  if (globalThis.indexedDB) {
+    return makeIndexedDbKeypairStorage();
+  }
+  if (globalThis.localStorage) {
+    return makeLocalStorageKeypairStorage();
+  }
+  throw new Error(
+    "No browser storage is available, please provide a KeypairStorage",
+  );
+};
+
+// Like EncryptedKeypairFile, but using what the WebCrypto API supports
+export interface BrowserEncryptedKeypair {
+  version: 1;
+  publicKey: string;
+  kdf: "pbkdf2-sha256";
+  kdfParams: {
+    iterations: number;
+    salt: string;
+  };
+  cipher: "aes-256-gcm";
+  cipherParams: {
+    iv: string;
+  };
+  // Includes the authentication tag, as WebCrypto returns it
+  ciphertext: string;
+}
+
+const deriveKeyWithPbkdf2 = async (
+  password: string,
+  salt: Uint8Array,
+  iterations: number,
+): Promise<CryptoKey> => {
+  const { subtle } = globalThis.crypto;
+  const passwordKey = await subtle.importKey(
+    "raw",
+    new TextEncoder().encode(password),
+    "PBKDF2",
+    false,
+    ["deriveKey"],
+  );
+  return subtle.deriveKey(
+    { name: "PBKDF2", salt, iterations, hash: "SHA-256" },
+    passwordKey,
+    { name: "AES-GCM", length: 256 },
+    false,
+    ["encrypt", "decrypt"],
+  );
+};
+
+const getBrowserStorageKey = (name: string) => `solana-keypair:${name}`;
+
+export interface BrowserStorageOptions {
+  storage?: KeypairStorage;
+}
+
+export const saveKeypairToBrowserStorage = async (
+  keypair: Keypair,
+  name: string,
+  password: string,
+  options?: BrowserStorageOptions & SaveKeypairToFileOptions,
+) => {
+  const storage = options?.storage || getDefaultKeypairStorage();
+  const storageKey = getBrowserStorageKey(name);
+  if (!options?.overwrite && (await storage.getItem(storageKey)) !== null) {
+    throw new Error(
+      `Keypair '${name}' already exists in browser storage, set 'overwrite' to replace it.`,
+    );
+  }
+
+  const salt = globalThis.crypto.getRandomValues(new Uint8Array(16));
+  const iv = globalThis.crypto.getRandomValues(new Uint8Array(12));
+  const iterations = PBKDF2_ITERATIONS;
+  const key = await deriveKeyWithPbkdf2(password, salt, iterations);
+  const publicKey = keypair.publicKey.toBase58();
+  const ciphertext = await globalThis.crypto.subtle.encrypt(
+    {
+      name: "AES-GCM",
+      iv,
+      additionalData: getEncryptedKeypairAdditionalData(publicKey),
+    },
+    key,
+    keypair.secretKey,
+  );
+
+  const encryptedKeypair: BrowserEncryptedKeypair = {
+    version: 1,
+    publicKey,
+    kdf: "pbkdf2-sha256",
+    kdfParams: { iterations, salt: encodeBase64(salt) },
+    cipher: "aes-256-gcm",
+    cipherParams: { iv: encodeBase64(iv) },
+    ciphertext: encodeBase64(new Uint8Array(ciphertext)),
+  };
+  await storage.setItem(storageKey, JSON.stringify(encryptedKeypair));
+};
+
+export const getKeypairFromBrowserStorage = async (
+  name: string,
+  password: string,
+  options?: BrowserStorageOptions & RedactOptions,
+): Promise<Keypair> => {
+  const storage = options?.storage || getDefaultKeypairStorage();
+  const storedValue = await storage.getItem(getBrowserStorageKey(name));
+  if (storedValue === null) {
+    throw new Error(`No keypair named '${name}' in browser storage.`);
+  }
+
+  const encryptedKeypair: BrowserEncryptedKeypair = JSON.parse(storedValue);
+  const { version, publicKey, kdf, kdfParams, cipher, cipherParams } =
+    encryptedKeypair;
+  if (version !== 1 || kdf !== "pbkdf2-sha256" || cipher !== "aes-256-gcm") {
+    throw new Error(
+      `Unsupported encrypted keypair format (version ${version}, ${kdf}, ${cipher})`,
+    );
+  }
+  // Like scrypt's parameters, the iterations come from storage, so could
+  // otherwise make us hang deriving the key
+  const { iterations } = kdfParams;
+  if (
+    !Number.isInteger(iterations) ||
+    iterations < 1 ||
+    iterations > PBKDF2_ITERATIONS
+  ) {
+    throw new Error(
+      `Unsupported PBKDF2 iterations (${iterations}), expected at most ${PBKDF2_ITERATIONS}`,
+    );
+  }
+
+  const key = await deriveKeyWithPbkdf2(
+    password,
+    decodeBase64(kdfParams.salt),
+    iterations,
+  );
+  let secretKey: ArrayBuffer;
+  try {
+    secretKey = await globalThis.crypto.subtle.decrypt(
+      {
+        name: "AES-GCM",
+        iv: decodeBase64(cipherParams.iv),
+        additionalData: getEncryptedKeypairAdditionalData(publicKey),
+      },
+      key,
+      decodeBase64(encryptedKeypair.ciphertext),
+    );
+  } catch (error) {
+    throw makeDecryptKeypairError(publicKey);
+  }
+  return redactIfRequired(
+    Keypair.fromSecretKey(new Uint8Array(secretKey)),
+    options,
+  );
+};
+
+export interface InitializeKeypairOptions extends RedactOptions {
+  envFileName?: string;
+  envVariableName?: string;
+  airdropAmount?: number | null;
+  minimumBalance?: number;
+  airdropOptions?: FundIfRequiredOptions;
+  keypairPath?: string;
+  envMnemonicVariableName?: string;
+  derivationPath?: string;
+  useSolanaCliConfig?: boolean;
+  browserStorage?: BrowserStorageOptions & {
+    password: string;
+    name?: string;
+  };
+}
+
+export const initializeKeypair = async (
+  connection: Connection,
+  options?: InitializeKeypairOptions,
+): Promise<Keypair> => {
+  let {
+    keypairPath,
+    envFileName,
+    envVariableName = DEFAULT_ENV_KEYPAIR_VARIABLE_NAME,
+    airdropAmount = DEFAULT_AIRDROP_AMOUNT,
+    minimumBalance = DEFAULT_MINIMUM_BALANCE,
+    envMnemonicVariableName,
+    derivationPath,
+    useSolanaCliConfig = false,
+    browserStorage,
+  } = options || {};
+
+  let keypair: Keypair;
+
+  if (keypairPath) {
+    keypair = await getKeypairFromFile(keypairPath);
+  } else if (browserStorage) {
+    // Browsers don't have env files, so we use browser storage instead
+    const { password, name = envVariableName } = browserStorage;
+    const storage = browserStorage.storage || getDefaultKeypairStorage();
+    if ((await storage.getItem(getBrowserStorageKey(name))) !== null) {
+      keypair = await getKeypairFromBrowserStorage(name, password, { storage });
+    } else {
+      keypair = Keypair.generate();
+      await saveKeypairToBrowserStorage(keypair, name, password, { storage });
+    }
+  } else if (useSolanaCliConfig) {
+    // Uses the keypair from 'solana config set'
+    keypair = await getKeypairFromFile();
+  } else if (envMnemonicVariableName && process.env[envMnemonicVariableName]) {
+    keypair = getKeypairFromMnemonic(
+      process.env[envMnemonicVariableName] as string,
+      { derivationPath },
+    );
+  } else if (process.env[envVariableName]) {
+    keypair = getKeypairFromEnvironment(envVariableName);
+  } else {
+    // The env file may not have been loaded into the environment (eg, by dotenv)
+    const secretKeyString = await getEnvFileVariable(
+      envFileName || DEFAULT_ENV_FILE_NAME,
+      envVariableName,
+    );
+    if (secretKeyString) {
+      keypair = parseSecretKey(secretKeyString);
+    } else {
+      keypair = Keypair.generate();
+      await addKeypairToEnvFile(keypair, envVariableName, envFileName);
+    }
+  }
+
+  if (airdropAmount) {
+    await fundIfRequired(
+      connection,
+      keypair.publicKey,
+      airdropAmount,
+      minimumBalance,
+      options?.airdropOptions,
+    );
+  }
+
+  return redactIfRequired(keypair, options);
+};
+
+export interface InitializeKeypairsOptions extends RedactOptions {
+  envFileName?: string;
+  keypairDirectory?: string;
+  airdropAmount?: number | null;
+  minimumBalance?: number;
+  airdropOptions?: FundIfRequiredOptions;
+}
+
+// Eg, 'admin' becomes 'ADMIN_PRIVATE_KEY'
+export const getEnvVariableNameForKeypair = (name: string): string => {
+  const prefix = name.toUpperCase().replace(/[^A-Z0-9]+/g, "_");
+  return `${prefix}_${DEFAULT_ENV_KEYPAIR_VARIABLE_NAME}`;
+};
+
+// Like initializeKeypair(), but for many named keypairs at once
+export const initializeKeypairs = async <Name extends string>(
+  connection: Connection,
+  names: ReadonlyArray<Name>,
+  options?: InitializeKeypairsOptions,
+): Promise<Record<Name, Keypair>> => {
+  const {
+    envFileName,
+    keypairDirectory,
+    airdropAmount = DEFAULT_AIRDROP_AMOUNT,
+    minimumBalance = DEFAULT_MINIMUM_BALANCE,
+  } = options || {};
+
+  // Names become env variable names or file names, so they need to be
+  // simple, and still different once they're env variable names
+  const namesByEnvVariableName = new Map<string, Name>();
+  for (const name of names) {
+    if (!/^[A-Za-z0-9_-]+$/.test(name)) {
+      throw new Error(
+        `Invalid keypair name '${name}', names can only have letters, numbers, '_' and '-'.`,
+      );
+    }
+    const envVariableName = getEnvVariableNameForKeypair(name);
+    const otherName = namesByEnvVariableName.get(envVariableName);
+    if (otherName !== undefined) {
+      throw new Error(
+        `Keypair names '${otherName}' and '${name}' would both be saved as '${envVariableName}'.`,
+      );
+    }
+    namesByEnvVariableName.set(envVariableName, name);
+  }
+
+  const keypairs = {} as Record<Name, Keypair>;
+  // One at a time, since they may all be saved to the same env file,
+  // and to be gentle on the faucet
+  for (const name of names) {
+    if (!keypairDirectory) {
+      keypairs[name] = await initializeKeypair(connection, {
+        envFileName,
+        envVariableName: getEnvVariableNameForKeypair(name),
+        airdropAmount,
+        minimumBalance,
+        airdropOptions: options?.airdropOptions,
+        redact: options?.redact,
+      });
+      continue;
+    }
+
+    const path = await import("path");
+    const { stat } = await import("fs/promises");
+    const keypairPath = path.join(keypairDirectory, `${name}.json`);
+    // Only make a new keypair if there isn't a file, so encrypted, corrupt
+    // or mismatched keypair files throw their own errors
+    let keypairFileExists = true;
+    try {
+      await stat(await expandHomeDirectory(keypairPath));
+    } catch (thrownObject) {
+      const error = thrownObject as NodeJS.ErrnoException;
+      if (error.code !== "ENOENT") {
+        throw error;
+      }
+      keypairFileExists = false;
+    }
+    if (keypairFileExists) {
+      keypairs[name] = await getKeypairFromFile(keypairPath, {
+        redact: options?.redact,
+      });
+    } else {
+      const keypair = Keypair.generate();
+      await saveKeypairToFile(keypair, keypairPath);
+      keypairs[name] = redactIfRequired(keypair, options);
+    }
+    if (airdropAmount) {
+      await fundIfRequired(
+        connection,
+        keypairs[name].publicKey,
+        airdropAmount,
+        minimumBalance,
+        options?.airdropOptions,
+      );
+    }
+  }
+  return keypairs;
+};
+
+export interface RotateKeypairOptions {
+  envFileName?: string;
+  envVariableName?: string;
+}
+
+// A token account that couldn't be moved, eg because it's frozen, or its
+// mint's extensions don't allow it to be transferred or closed
+export interface FailedTokenAccount {
+  tokenAccount: PublicKey;
+  mint: PublicKey;
+  programId: PublicKey;
+  error: Error;
+}
+
+// Make a new keypair to replace one that has leaked, moving everything
+// the old keypair owns to the new one
+export const rotateKeypair = async (
+  connection: Connection,
+  options?: RotateKeypairOptions,
+) => {
+  const {
+    envFileName = DEFAULT_ENV_FILE_NAME,
+    envVariableName = DEFAULT_ENV_KEYPAIR_VARIABLE_NAME,
+  } = options || {};
+
+  // The env file may not have been loaded into the environment (eg, by dotenv)
+  const secretKeyString = await getEnvFileVariable(
+    envFileName,
+    envVariableName,
+  );
+  const oldKeypair = secretKeyString
+    ? parseSecretKey(secretKeyString)
+    : getKeypairFromEnvironment(envVariableName);
+  const newKeypair = Keypair.generate();
+
+  // Save the new keypair before moving anything to it, so nothing can be
+  // lost if moving funds fails part way through. The old keypair is
+  // kept, commented out, in case it's needed again.
+  await commentOutEnvFileVariable(
+    envFileName,
+    envVariableName,
+    `Rotated at ${new Date().toISOString()}, Solana Address: ${oldKeypair.publicKey.toBase58()}`,
+  );
+  await addKeypairToEnvFile(newKeypair, envVariableName, envFileName);
+  if (process.env[envVariableName]) {
+    process.env[envVariableName] = keypairToSecretKeyJSON(newKeypair);
+  }
+
+  const signatures: Array<string> = [];
+  const failedTokenAccounts: Array<FailedTokenAccount> = [];
+
+  // Move every token balance, then close the old token accounts, which
+  // sends their rent to the new keypair too. One account per transaction,
+  // so an account that can't be moved doesn't stop the others moving.
+  for (const programId of [TOKEN_PROGRAM_ID, TOKEN_2022_PROGRAM_ID]) {
+    const tokenAccounts = await connection.getParsedTokenAccountsByOwner(
+      oldKeypair.publicKey,
+      { programId },
+    );
+    for (const { pubkey, account } of tokenAccounts.value) {
+      const { mint, tokenAmount } = (account.data as ParsedAccountData).parsed
+        .info;
+      const mintAddress = new PublicKey(mint);
+      const amount = BigInt(tokenAmount.amount);
+      const newTokenAccount = getAssociatedTokenAddressSync(
+        mintAddress,
+        newKeypair.publicKey,
+        false,
+        programId,
+      );
+      const instructions = [
+        ...(amount > 0n
+          ? [
+              createAssociatedTokenAccountIdempotentInstruction(
+                oldKeypair.publicKey,
+                newTokenAccount,
+                newKeypair.publicKey,
+                mintAddress,
+                programId,
+              ),
+              createTransferCheckedInstruction(
+                pubkey,
+                mintAddress,
+                newTokenAccount,
+                oldKeypair.publicKey,
+                amount,
+                tokenAmount.decimals,
+                [],
+                programId,
+              ),
+            ]
+          : []),
+        createCloseAccountInstruction(
+          pubkey,
+          newKeypair.publicKey,
+          oldKeypair.publicKey,
+          [],
+          programId,
+        ),
+      ];
+      try {
+        const signature = await makeAndSendAndConfirmTransaction(
+          connection,
+          instructions,
+          [oldKeypair],
+          oldKeypair,
+        );
+        signatures.push(signature);
+      } catch (error) {
+        failedTokenAccounts.push({
+          tokenAccount: pubkey,
+          mint: mintAddress,
+          programId,
+          error: error as Error,
+        });
+      }
+    }
+  }
+
+  // Finally move the SOL, leaving just enough to pay for the transaction
+  const balance = await connection.getBalance(
+    oldKeypair.publicKey,
+    "finalized",
+  );
+  const makeTransferInstruction = (lamports: number) =>
+    SystemProgram.transfer({
+      fromPubkey: oldKeypair.publicKey,
+      toPubkey: newKeypair.publicKey,
+      lamports,
+    });
+  const { blockhash } = await connection.getLatestBlockhash();
+  const message = new TransactionMessage({
+    payerKey: oldKeypair.publicKey,
+    recentBlockhash: blockhash,
+    instructions: [makeTransferInstruction(balance)],
+  }).compileToV0Message();
+  const fee = (await connection.getFeeForMessage(message)).value;
+  if (fee === null) {
+    throw new Error(
+      `Could not get the fee to move SOL from '${oldKeypair.publicKey.toBase58()}'`,
+    );
+  }
+  if (balance > fee) {
+    const signature = await makeAndSendAndConfirmTransaction(
+      connection,
+      [makeTransferInstruction(balance - fee)],
+      [oldKeypair],
+      oldKeypair,
+    );
+    signatures.push(signature);
+  }
+
+  return { oldKeypair, newKeypair, signatures, failedTokenAccounts };
+};
+
+export interface AirdropIfRequiredOptions {
+  // How many times to retry if the RPC server rate limits us. Connection
+  // also retries these itself, unless 'disableRetryOnRateLimit' is set.
+  retries?: number;
+  // Milliseconds before the first retry, doubled for each retry after that
+  retryDelay?: number;
+  maxRetryDelay?: number;
+  // How sure to be that the airdrop has landed before returning.
+  // 'confirmed' is much faster, eg, on a local validator.
+  commitment?: Commitment;
+  // Milliseconds to wait for the airdrop to be confirmed
+  timeout?: number;
+}
+
+export interface AirdropResult {
+  balance: number;
+  // Whether SOL was sent, by an airdrop or a funder
+  airdropped: boolean;
+  signature: string | null;
+  // The slot the balance was read at
+  slot: number;
+}
+
+// 'rate-limited' is the RPC server limiting requests (HTTP 429),
+// 'airdrop-limit' is the faucet limiting airdrops
+export type AirdropErrorReason =
+  | "rate-limited"
+  | "airdrop-limit"
+  | "timeout"
+  | "mainnet"
+  | "failed";
+
+export class AirdropError extends Error {
+  reason: AirdropErrorReason;
+  attempts: number;
+
+  constructor(
+    message: string,
+    reason: AirdropErrorReason,
+    attempts: number,
+    options?: ErrorOptions,
+  ) {
+    super(message, options);
+    this.name = "AirdropError";
+    this.reason = reason;
+    this.attempts = attempts;
+  }
+}
+
+const AIRDROP_ERROR_DESCRIPTIONS: Record<AirdropErrorReason, string> = {
+  "rate-limited":
+    "the RPC server is rate limiting requests (HTTP 429 Too Many Requests)",
+  "airdrop-limit": "the faucet's airdrop request limit was reached",
+  timeout: "the airdrop wasn't confirmed in time",
+  mainnet:
+    "there are no airdrops on mainnet, send SOL from another wallet instead (eg, with fundIfRequired())",
+  failed: "the airdrop request failed",
+};
+
+const getAirdropErrorReason = (error: unknown): AirdropErrorReason => {
+  const message = error instanceof Error ? error.message : String(error);
+  // Check the faucet's limits first, since the devnet faucet's daily limit is
+  // also an HTTP 429, eg 'You've either reached your airdrop limit today or
+  // the airdrop faucet has run dry', 'airdrop request limit reached' or
+  // 'airdrop request failed. This can happen when the rate limit is reached.'
+  if (
+    /airdrop (request )?limit|faucet has run dry|rate limit is reached/i.test(
+      message,
+    )
+  ) {
+    return "airdrop-limit";
+  }
+  if (/\b429\b|too many requests/i.test(message)) {
+    return "rate-limited";
+  }
+  return "failed";
+};
+
+// Exponential backoff, with jitter so many clients that were rate limited
+// at the same time don't all retry at the same time too
+const getAirdropRetryDelay = (
+  retry: number,
+  retryDelay: number,
+  maxRetryDelay: number,
+) => {
+  const delay = Math.min(retryDelay * 2 ** retry, maxRetryDelay);
+  return delay / 2 + Math.random() * (delay / 2);
+};
+
+const requestAirdropWithRetries = async (
+  connection: Connection,
+  publicKey: PublicKey,
+  amount: number,
+  options?: AirdropIfRequiredOptions,
+): Promise<{ signature: string; attempts: number }> => {
+  const {
+    retries = DEFAULT_AIRDROP_RETRIES,
+    retryDelay = DEFAULT_AIRDROP_RETRY_DELAY,
+    maxRetryDelay = DEFAULT_MAX_AIRDROP_RETRY_DELAY,
+  } = options || {};
+
+  for (let attempt = 1; ; attempt++) {
+    try {
+      const signature = await connection.requestAirdrop(publicKey, amount);
+      return { signature, attempts: attempt };
+    } catch (error) {
+      const reason = getAirdropErrorReason(error);
+      // Only the RPC server's rate limits are worth retrying. Other errors,
+      // including the faucet's daily limit, will just happen again.
+      if (reason !== "rate-limited" || attempt > retries) {
+        const attempts = attempt === 1 ? "" : ` after ${attempt} attempts`;
+        throw new AirdropError(
+          `Airdrop to '${publicKey.toBase58()}' failed${attempts}: ${AIRDROP_ERROR_DESCRIPTIONS[reason]}.`,
+          reason,
+          attempt,
+          { cause: error },
+        );
+      }
+      await new Promise((resolve) =>
+        setTimeout(
+          resolve,
+          getAirdropRetryDelay(attempt - 1, retryDelay, maxRetryDelay),
+        ),
+      );
+    }
+  }
+};
+
+// Not exported as we don't want to encourage people to
+// request airdrops when they don't need them, ie - don't bother
+// the faucet unless you really need to!
+const requestAndConfirmAirdrop = async (
+  connection: Connection,
+  publicKey: PublicKey,
+  amount: number,
+  options?: AirdropIfRequiredOptions,
+): Promise<AirdropResult> => {
+  const { commitment = DEFAULT_AIRDROP_COMMITMENT, timeout } = options || {};
+  // Mainnet RPC servers do reject airdrops, but not with a helpful error
+  if ((await getClusterFromConnection(connection)) === "mainnet-beta") {
+    throw new AirdropError(
+      `Airdrop to '${publicKey.toBase58()}' failed: ${AIRDROP_ERROR_DESCRIPTIONS.mainnet}.`,
+      "mainnet",
+      0,
+    );
+  }
+  const { signature, attempts } = await requestAirdropWithRetries(
+    connection,
+    publicKey,
+    amount,
+    options,
+  );
+  // Wait for airdrop confirmation
+  const latestBlockHash = await connection.getLatestBlockhash();
+  const abortSignal =
+    timeout === undefined ? undefined : AbortSignal.timeout(timeout);
+  try {
+    await connection.confirmTransaction(
+      {
+        blockhash: latestBlockHash.blockhash,
+        lastValidBlockHeight: latestBlockHash.lastValidBlockHeight,
+        signature,
+        abortSignal,
+      },
+      commitment,
+    );
+  } catch (error) {
+    if (abortSignal?.aborted) {
+      throw new AirdropError(
+        `Airdrop to '${publicKey.toBase58()}' failed: the airdrop wasn't confirmed within ${timeout}ms.`,
+        "timeout",
+        attempts,
+        { cause: error },
+      );
+    }
+    throw error;
+  }
+  const { value: balance, context } = await connection.getBalanceAndContext(
+    publicKey,
+    commitment,
+  );
+  return { balance, airdropped: true, signature, slot: context.slot };
+};
+
+export const airdropIfRequired = async (
+  connection: Connection,
+  publicKey: PublicKey,
+  airdropAmount: number,
+  minimumBalance: number,
+  options?: AirdropIfRequiredOptions,
+): Promise<AirdropResult> => {
+  const { value: balance, context } = await connection.getBalanceAndContext(
+    publicKey,
+    "confirmed",
+  );
+  if (balance < minimumBalance) {
+    return requestAndConfirmAirdrop(
+      connection,
+      publicKey,
+      airdropAmount,
+      options,
+    );
+  }
+  return { balance, airdropped: false, signature: null, slot: context.slot };
+};
+
+export interface FundIfRequiredOptions extends AirdropIfRequiredOptions {
+  // Sends SOL if the airdrop fails, eg, the Solana CLI wallet from
+  // getKeypairFromFile()
+  funder?: Signer | AsyncSigner;
+  // Don't use the faucet at all, just the funder
+  alwaysUseFunder?: boolean;
+}
+
+// Like airdropIfRequired(), but can send SOL from a funder instead,
+// for when the faucet isn't available (or you don't want to use it)
+export const fundIfRequired = async (
+  connection: Connection,
+  publicKey: PublicKey,
+  amount: number,
+  minimumBalance: number,
+  options?: FundIfRequiredOptions,
+): Promise<AirdropResult> => {
+  const {
+    funder,
+    alwaysUseFunder = false,
+    commitment = DEFAULT_AIRDROP_COMMITMENT,
+  } = options || {};
+  if (alwaysUseFunder && !funder) {
+    throw new Error("'alwaysUseFunder' is set, but there is no 'funder'.");
+  }
+
+  const { value: balance, context } = await connection.getBalanceAndContext(
+    publicKey,
+    "confirmed",
+  );
+  if (balance >= minimumBalance) {
+    return { balance, airdropped: false, signature: null, slot: context.slot };
+  }
+
+  if (!funder) {
+    return requestAndConfirmAirdrop(connection, publicKey, amount, options);
+  }
+  let airdropError: AirdropError | null = null;
+  if (!alwaysUseFunder) {
+    try {
+      return await requestAndConfirmAirdrop(
+        connection,
+        publicKey,
+        amount,
+        options,
+      );
+    } catch (error) {
+      // A timed out airdrop was sent, and may still land, so using the
+      // funder too could fund the account twice
+      if (!(error instanceof AirdropError) || error.reason === "timeout") {
+        throw error;
+      }
+      airdropError = error;
+    }
+  }
+
+  let signature: string;
+  try {
+    signature = await makeAndSendAndConfirmTransaction(
+      connection,
+      [
+        SystemProgram.transfer({
+          fromPubkey: funder.publicKey,
+          toPubkey: publicKey,
+          lamports: amount,
+        }),
+      ],
+      [funder],
+      funder,
+      commitment,
+    );
+  } catch (error) {
+    if (!airdropError) {
+      throw error;
+    }
+    // Keep why the airdrop failed too
+    throw new Error(
+      `${airdropError.message} Sending SOL from the funder failed too: ${(error as Error).message}`,
+      { cause: error },
+    );
+  }
+  // Like airdrops, the transfer is confirmed before we return
+  const newBalance = await connection.getBalanceAndContext(
+    publicKey,
+    commitment,
+  );
+  return {
+    balance: newBalance.value,
+    airdropped: true,
+    signature,
+    slot: newBalance.context.slot,
+  };
+};
+
+export interface AirdropIfRequiredManyOptions extends FundIfRequiredOptions {
+  // How many airdrops to request at once
+  concurrency?: number;
+}
+
+export interface AirdropIfRequiredManyResult extends AirdropResult {
+  publicKey: PublicKey;
+  // Why this account couldn't be funded, so one failure doesn't lose
+  // the results for the others
+  error: Error | null;
+}
+
+const getBalancesAndSlots = async (
+  connection: Connection,
+  publicKeys: Array<PublicKey>,
+  commitment: Commitment,
+): Promise<Array<{ balance: number; slot: number }>> => {
+  const balances: Array<{ balance: number; slot: number }> = [];
+  for (
+    let index = 0;
+    index < publicKeys.length;
+    index += MAX_ACCOUNTS_PER_REQUEST
+  ) {
+    const { value: accounts, context } =
+      await connection.getMultipleAccountsInfoAndContext(
+        publicKeys.slice(index, index + MAX_ACCOUNTS_PER_REQUEST),
+        commitment,
+      );
+    for (const account of accounts) {
+      // Accounts that don't exist yet have no SOL
+      balances.push({ balance: account?.lamports || 0, slot: context.slot });
+    }
   }
+  return balances;
+};
+
+const runWithConcurrency = async <Item>(
+  items: Array<Item>,
+  concurrency: number,
+  task: (item: Item) => Promise<void>,
+) => {
+  let nextIndex = 0;
+  const workers = Array.from(
+    { length: Math.min(concurrency, items.length) },
+    async () => {
+      while (nextIndex < items.length) {
+        await task(items[nextIndex++]);
+      }
+    },
+  );
+  await Promise.all(workers);
+};
+
+const getTransactionSize = (
+  payer: PublicKey,
+  instructions: Array<TransactionInstruction>,
+) => {
+  const messageV0 = new TransactionMessage({
+    payerKey: payer,
+    // Any blockhash is the same size
+    recentBlockhash: PublicKey.default.toBase58(),
+    instructions,
+  }).compileToV0Message();
+  return new VersionedTransaction(messageV0).serialize().length;
+};
 
-  return keypair;
+// Split items into as few transactions as their instructions will fit in
+const packIntoTransactions = <Item>(
+  payer: PublicKey,
+  items: Array<Item>,
+  makeInstruction: (item: Item) => TransactionInstruction,
+): Array<Array<Item>> => {
+  const batches: Array<Array<Item>> = [];
+  let batch: Array<Item> = [];
+  for (const item of items) {
+    const candidate = [...batch, item];
+    const size = getTransactionSize(payer, candidate.map(makeInstruction));
+    if (batch.length && size > PACKET_DATA_SIZE) {
+      batches.push(batch);
+      batch = [item];
+    } else {
+      batch = candidate;
+    }
+  }
+  if (batch.length) {
+    batches.push(batch);
+  }
+  return batches;
 };
 
-// Not exported as we don't want to encourage people to
-// request airdrops when they don't need them, ie - don't bother
-// the faucet unless you really need to!
-const requestAndConfirmAirdrop = async (
+// Like fundIfRequired(), but for many accounts at once
+export const airdropIfRequiredMany = async (
   connection: Connection,
-  publicKey: PublicKey,
+  publicKeys: Array<PublicKey>,
   amount: number,
-) => {
-  const airdropTransactionSignature = await connection.requestAirdrop(
-    publicKey,
-    amount,
+  minimumBalance: number,
+  options?: AirdropIfRequiredManyOptions,
+): Promise<Array<AirdropIfRequiredManyResult>> => {
+  const {
+    concurrency = DEFAULT_AIRDROP_CONCURRENCY,
+    funder,
+    alwaysUseFunder = false,
+    commitment = DEFAULT_AIRDROP_COMMITMENT,
+  } = options || {};
+  if (alwaysUseFunder && !funder) {
+    throw new Error("'alwaysUseFunder' is set, but there is no 'funder'.");
+  }
+  if (!Number.isInteger(concurrency) || concurrency < 1) {
+    throw new Error(
+      `'concurrency' must be a whole number, at least 1, but was ${concurrency}.`,
+    );
+  }
+
+  const balancesAndSlots = await getBalancesAndSlots(
+    connection,
+    publicKeys,
+    "confirmed",
   );
-  // Wait for airdrop confirmation
-  const latestBlockHash = await connection.getLatestBlockhash();
-  await connection.confirmTransaction(
-    {
-      blockhash: latestBlockHash.blockhash,
-      lastValidBlockHeight: latestBlockHash.lastValidBlockHeight,
-      signature: airdropTransactionSignature,
-    },
-    // "finalized" is slow but we must be absolutely sure
-    // the airdrop has gone through
-    "finalized",
+  const results: Array<AirdropIfRequiredManyResult> = publicKeys.map(
+    (publicKey, index) => ({
+      publicKey,
+      ...balancesAndSlots[index],
+      airdropped: false,
+      signature: null,
+      error: null,
+    }),
+  );
+  const resultsToFund = results.filter(
+    ({ balance }) => balance < minimumBalance,
   );
-  return connection.getBalance(publicKey, "finalized");
-};
 
-export const airdropIfRequired = async (
-  connection: Connection,
-  publicKey: PublicKey,
-  airdropAmount: number,
-  minimumBalance: number,
-): Promise<number> => {
-  const balance = await connection.getBalance(publicKey, "confirmed");
-  if (balance < minimumBalance) {
-    return requestAndConfirmAirdrop(connection, publicKey, airdropAmount);
+  let resultsToTransferTo = resultsToFund;
+  if (!alwaysUseFunder) {
+    resultsToTransferTo = [];
+    await runWithConcurrency(resultsToFund, concurrency, async (result) => {
+      try {
+        Object.assign(
+          result,
+          await requestAndConfirmAirdrop(
+            connection,
+            result.publicKey,
+            amount,
+            options,
+          ),
+        );
+      } catch (error) {
+        // Like fundIfRequired(), a timed out airdrop may still land, so
+        // only use the funder for airdrops that failed
+        const canUseFunder =
+          funder && error instanceof AirdropError && error.reason !== "timeout";
+        if (canUseFunder) {
+          resultsToTransferTo.push(result);
+        } else {
+          result.error = error as Error;
+        }
+      }
+    });
+  }
+
+  if (!funder || !resultsToTransferTo.length) {
+    return results;
+  }
+
+  const makeTransferInstruction = (result: AirdropIfRequiredManyResult) =>
+    SystemProgram.transfer({
+      fromPubkey: funder.publicKey,
+      toPubkey: result.publicKey,
+      lamports: amount,
+    });
+  const batches = packIntoTransactions(
+    funder.publicKey,
+    resultsToTransferTo,
+    makeTransferInstruction,
+  );
+  const transferredResults: Array<AirdropIfRequiredManyResult> = [];
+  for (const batch of batches) {
+    try {
+      const signature = await makeAndSendAndConfirmTransaction(
+        connection,
+        batch.map(makeTransferInstruction),
+        [funder],
+        funder,
+        commitment,
+      );
+      for (const result of batch) {
+        result.airdropped = true;
+        result.signature = signature;
+        transferredResults.push(result);
+      }
+    } catch (error) {
+      for (const result of batch) {
+        result.error = error as Error;
+      }
+    }
+  }
+  if (!transferredResults.length) {
+    return results;
   }
-  return balance;
+
+  // Like airdrops, the transfers are confirmed before we return
+  const newBalancesAndSlots = await getBalancesAndSlots(
+    connection,
+    transferredResults.map(({ publicKey }) => publicKey),
+    commitment,
+  );
+  transferredResults.forEach((result, index) => {
+    Object.assign(result, newBalancesAndSlots[index]);
+  });
+  return results;
 };
 
 export const confirmTransaction = async (
@@ -328,9 +3055,262 @@ export const confirmTransaction = async (
   return signature;
 };
 
+export interface MakeKeypairsOptions {
+  seed?: string;
+}
+
 // Shout out to Dean from WBA for this technique
-export const makeKeypairs = (amount: number): Array<Keypair> => {
-  return Array.from({ length: amount }, () => Keypair.generate());
+export const makeKeypairs = (
+  amount: number,
+  options?: MakeKeypairsOptions,
+): Array<Keypair> => {
+  const seed = options?.seed;
+  return Array.from({ length: amount }, (_, index) => {
+    if (seed === undefined) {
+      return Keypair.generate();
+    }
+    // The same seed and index always make the same keypair.
+    // Only use this for tests, since anyone who knows the seed has the keypair!
+    return Keypair.fromSeed(sha256(`${seed}:${index}`));
+  });
+};
+
+const BASE58_ALPHABET =
+  "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+// How often grinding threads report how many keypairs they've tried
+const GRIND_PROGRESS_INTERVAL = 1_000;
+
+// Runs in each worker thread. Uses node's native ed25519 support
+// (rather than web3.js) so it doesn't need to load any modules.
+const GRIND_WORKER_SOURCE = `
+const { parentPort, workerData } = require("worker_threads");
+const { generateKeyPairSync } = require("crypto");
+const { alphabet, startsWith, endsWith, ignoreCase, progressInterval } =
+  workerData;
+
+const encodeBase58 = (bytes) => {
+  const digits = [];
+  for (const byte of bytes) {
+    let carry = byte;
+    for (let index = 0; index < digits.length; index++) {
+      carry += digits[index] << 8;
+      digits[index] = carry % 58;
+      carry = (carry / 58) | 0;
+    }
+    while (carry > 0) {
+      digits.push(carry % 58);
+      carry = (carry / 58) | 0;
+    }
+  }
+  let result = "";
+  for (const byte of bytes) {
+    if (byte !== 0) break;
+    result += alphabet[0];
+  }
+  for (let index = digits.length - 1; index >= 0; index--) {
+    result += alphabet[digits[index]];
+  }
+  return result;
+};
+
+let attempts = 0;
+while (true) {
+  const { privateKey } = generateKeyPairSync("ed25519");
+  const { x, d } = privateKey.export({ format: "jwk" });
+  let address = encodeBase58(Buffer.from(x, "base64url"));
+  if (ignoreCase) {
+    address = address.toLowerCase();
+  }
+  attempts++;
+  if (address.startsWith(startsWith) && address.endsWith(endsWith)) {
+    parentPort.postMessage({ type: "found", seed: d, attempts });
+    break;
+  }
+  if (attempts === progressInterval) {
+    parentPort.postMessage({ type: "progress", attempts });
+    attempts = 0;
+  }
+}
+`;
+
+export interface GrindKeypairProgress {
+  attempts: number;
+  expectedAttempts: number;
+  elapsedMilliseconds: number;
+  attemptsPerSecond: number;
+  estimatedRemainingMilliseconds: number;
+}
+
+export interface GrindKeypairOptions {
+  startsWith?: string;
+  endsWith?: string;
+  ignoreCase?: boolean;
+  threads?: number;
+  onProgress?: (progress: GrindKeypairProgress) => void;
+  signal?: AbortSignal;
+}
+
+// How many keypairs we expect to have to make, on average, to find a match.
+// Each character has a 1 in 58 chance of matching (or 2 in 58 if we ignore case and
+// the character has both an uppercase and lowercase form in base58).
+// This is an estimate: the first character of an address isn't evenly distributed.
+export const getExpectedGrindAttempts = (
+  options: Pick<GrindKeypairOptions, "startsWith" | "endsWith" | "ignoreCase">,
+): number => {
+  const { startsWith = "", endsWith = "", ignoreCase = false } = options;
+  let expectedAttempts = 1;
+  for (const character of startsWith + endsWith) {
+    const matchingCharacters = Array.from(BASE58_ALPHABET).filter(
+      (alphabetCharacter) =>
+        ignoreCase
+          ? alphabetCharacter.toLowerCase() === character.toLowerCase()
+          : alphabetCharacter === character,
+    ).length;
+    if (!matchingCharacters) {
+      throw new Error(
+        `Invalid character '${character}', addresses are base58 which doesn't use 0, O, I or l`,
+      );
+    }
+    expectedAttempts *= BASE58_ALPHABET.length / matchingCharacters;
+  }
+  return expectedAttempts;
+};
+
+const isVanityMatch = (
+  address: string,
+  startsWith: string,
+  endsWith: string,
+  ignoreCase: boolean,
+) => {
+  if (ignoreCase) {
+    address = address.toLowerCase();
+  }
+  return address.startsWith(startsWith) && address.endsWith(endsWith);
+};
+
+// Make a keypair whose address starts and/or ends with particular characters
+export const grindKeypair = async (
+  options: GrindKeypairOptions,
+): Promise<Keypair> => {
+  const expectedAttempts = getExpectedGrindAttempts(options);
+  const { ignoreCase = false, onProgress, signal } = options;
+  let { startsWith = "", endsWith = "" } = options;
+  if (ignoreCase) {
+    startsWith = startsWith.toLowerCase();
+    endsWith = endsWith.toLowerCase();
+  }
+
+  if (signal?.aborted) {
+    throw new Error("Keypair grinding was cancelled");
+  }
+
+  const startTime = Date.now();
+  let attempts = 0;
+  const reportProgress = (newAttempts: number) => {
+    attempts += newAttempts;
+    if (!onProgress) {
+      return;
+    }
+    const elapsedMilliseconds = Date.now() - startTime;
+    const attemptsPerSecond = (attempts / (elapsedMilliseconds || 1)) * 1000;
+    onProgress({
+      attempts,
+      expectedAttempts,
+      elapsedMilliseconds,
+      attemptsPerSecond,
+      estimatedRemainingMilliseconds:
+        (Math.max(expectedAttempts - attempts, 0) / attemptsPerSecond) * 1000,
+    });
+  };
+
+  let workerThreads: typeof import("worker_threads") | null = null;
+  try {
+    workerThreads = await import("worker_threads");
+  } catch (error) {
+    // Not in node.js, so we'll grind on this thread instead.
+    // There's no Web Worker support yet, so 'threads' is ignored here.
+  }
+
+  if (!workerThreads) {
+    // Grind in batches, yielding between them so progress,
+    // cancellation and the rest of the app can still run
+    while (true) {
+      for (let index = 0; index < GRIND_PROGRESS_INTERVAL; index++) {
+        const keypair = Keypair.generate();
+        if (
+          isVanityMatch(
+            keypair.publicKey.toBase58(),
+            startsWith,
+            endsWith,
+            ignoreCase,
+          )
+        ) {
+          reportProgress(index + 1);
+          return keypair;
+        }
+      }
+      reportProgress(GRIND_PROGRESS_INTERVAL);
+      await new Promise((resolve) => setTimeout(resolve, 0));
+      if (signal?.aborted) {
+        throw new Error("Keypair grinding was cancelled");
+      }
+    }
+  }
+
+  const { Worker } = workerThreads;
+  const { availableParallelism } = await import("os");
+  const threads = options.threads || availableParallelism();
+
+  return new Promise<Keypair>((resolve, reject) => {
+    const workers = Array.from(
+      { length: threads },
+      () =>
+        new Worker(GRIND_WORKER_SOURCE, {
+          eval: true,
+          workerData: {
+            alphabet: BASE58_ALPHABET,
+            startsWith,
+            endsWith,
+            ignoreCase,
+            progressInterval: GRIND_PROGRESS_INTERVAL,
+          },
+        }),
+    );
+
+    let isFinished = false;
+    const stop = () => {
+      isFinished = true;
+      signal?.removeEventListener("abort", onAbort);
+      workers.forEach((worker) => worker.terminate());
+    };
+
+    const onAbort = () => {
+      stop();
+      reject(new Error("Keypair grinding was cancelled"));
+    };
+    signal?.addEventListener("abort", onAbort, { once: true });
+
+    workers.forEach((worker) => {
+      worker.on("message", (message) => {
+        // Other threads may still report back after we've finished
+        if (isFinished) {
+          return;
+        }
+        if (message.type === "progress") {
+          reportProgress(message.attempts);
+          return;
+        }
+        stop();
+        reportProgress(message.attempts);
+        resolve(Keypair.fromSeed(Buffer.from(message.seed, "base64url")));
+      });
+      worker.on("error", (error) => {
+        stop();
+        reject(error);
+      });
+    });
+  });
 };
 
 export const getLogs = async (
@@ -377,7 +3357,30 @@ export const getSimulationComputeUnits = async (
     sigVerify: false,
   });
 
-  getErrorFromRPCResponse(rpcResponse);
+  try {
+    getErrorFromRPCResponse(rpcResponse);
+  } catch (error) {
+    // Link to the transaction inspector, so it's easier to see what went wrong
+    let inspectorLink: string;
+    try {
+      inspectorLink = await getExplorerLinkForConnection(
+        "inspector",
+        testTransaction,
+        connection,
+      );
+    } catch (inspectorLinkError) {
+      throw error;
+    }
+    // Keep the error's type, so callers can still see what went wrong
+    if (error instanceof TransactionFailedError) {
+      error.message += `\nInspect the transaction at ${inspectorLink}`;
+      throw error;
+    }
+    throw new Error(
+      `${(error as Error).message}\nInspect the transaction at ${inspectorLink}`,
+      { cause: error },
+    );
+  }
   return rpcResponse.value.unitsConsumed || null;
 };
 
@@ -400,35 +3403,333 @@ const makeMintInstructions = (
       null,
       TOKEN_PROGRAM,
     ),
+    ...makeMintToInstructions(
+      mintAddress,
+      ataAddress,
+      authority,
+      amount,
+      authority,
+      payer,
+      TOKEN_PROGRAM,
+    ),
+  ];
+};
+
+// Create the ATA if it doesn't exist yet, and mint tokens to it
+const makeMintToInstructions = (
+  mintAddress: PublicKey,
+  ataAddress: PublicKey,
+  owner: PublicKey,
+  amount: number | bigint,
+  mintAuthority: PublicKey,
+  payer: PublicKey,
+  tokenProgram: PublicKey,
+): Array<TransactionInstruction> => {
+  return [
     // Create the ATA
     createAssociatedTokenAccountIdempotentInstruction(
       payer,
       ataAddress,
-      authority,
+      owner,
       mintAddress,
-      TOKEN_PROGRAM,
+      tokenProgram,
     ),
     // Mint some tokens to the ATA
     createMintToInstruction(
       mintAddress,
       ataAddress,
-      authority,
+      mintAuthority,
       amount,
       [],
-      TOKEN_PROGRAM,
+      tokenProgram,
     ),
   ];
 };
 
+export interface MintTokensIfRequiredOptions {
+  mint: PublicKey;
+  owner: PublicKey;
+  // In the smallest unit of the token, like lamports for SOL
+  minimum: number | bigint;
+  // The balance to top up to, defaults to the minimum
+  amount?: number | bigint;
+  mintAuthority: Signer | AsyncSigner;
+  // Defaults to the mint authority
+  payer?: Signer | AsyncSigner;
+}
+
+export interface MintTokensIfRequiredResult {
+  tokenAccount: PublicKey;
+  balance: bigint;
+  minted: bigint;
+  signature: string | null;
+}
+
+// Like airdropIfRequired(), but for tokens
+export const mintTokensIfRequired = async (
+  connection: Connection,
+  options: MintTokensIfRequiredOptions,
+): Promise<MintTokensIfRequiredResult> => {
+  const { mint, owner, mintAuthority, payer = mintAuthority } = options;
+  const minimum = BigInt(options.minimum);
+  const amount = BigInt(options.amount ?? options.minimum);
+  if (amount < minimum) {
+    throw new Error(
+      `'amount' (${amount}) must be at least 'minimum' (${minimum}).`,
+    );
+  }
+
+  // Mints for the Token Extensions program need the Token Extensions
+  // program for their token accounts too
+  const mintAccount = await connection.getAccountInfo(mint);
+  if (!mintAccount) {
+    throw new Error(`Mint '${mint.toBase58()}' does not exist.`);
+  }
+  const tokenProgram = mintAccount.owner;
+  if (
+    !tokenProgram.equals(TOKEN_PROGRAM_ID) &&
+    !tokenProgram.equals(TOKEN_2022_PROGRAM_ID)
+  ) {
+    throw new Error(
+      `'${mint.toBase58()}' is not a mint, it is owned by '${tokenProgram.toBase58()}'.`,
+    );
+  }
+
+  // The owner may be a PDA
+  const tokenAccount = getAssociatedTokenAddressSync(
+    mint,
+    owner,
+    true,
+    tokenProgram,
+  );
+  const tokenAccountInfo = await connection.getAccountInfo(tokenAccount);
+  const balance = tokenAccountInfo
+    ? unpackAccount(tokenAccount, tokenAccountInfo, tokenProgram).amount
+    : 0n;
+  if (balance >= minimum) {
+    return { tokenAccount, balance, minted: 0n, signature: null };
+  }
+
+  // Only mint the difference
+  const minted = amount - balance;
+  const signature = await makeAndSendAndConfirmTransaction(
+    connection,
+    makeMintToInstructions(
+      mint,
+      tokenAccount,
+      owner,
+      minted,
+      mintAuthority.publicKey,
+      payer.publicKey,
+      tokenProgram,
+    ),
+    payer === mintAuthority ? [payer] : [mintAuthority, payer],
+    payer,
+  );
+  return { tokenAccount, balance: amount, minted, signature };
+};
+
+// A signer that doesn't need the secret key in memory, eg, one that
+// asks a password manager or a remote service to sign
+export interface AsyncSigner {
+  publicKey: PublicKey;
+  signMessage(message: Uint8Array): Promise<Uint8Array>;
+  signTransaction<T extends Transaction | VersionedTransaction>(
+    transaction: T,
+  ): Promise<T>;
+}
+
+export const isAsyncSigner = (
+  signer: Signer | AsyncSigner,
+): signer is AsyncSigner => {
+  return "signTransaction" in signer;
+};
+
+// Signing a transaction is signing its serialized message
+const makeAsyncSigner = (
+  publicKey: PublicKey,
+  signMessage: (message: Uint8Array) => Promise<Uint8Array>,
+): AsyncSigner => {
+  return {
+    publicKey,
+    signMessage,
+    signTransaction: async <T extends Transaction | VersionedTransaction>(
+      transaction: T,
+    ): Promise<T> => {
+      if (transaction instanceof VersionedTransaction) {
+        const signature = await signMessage(transaction.message.serialize());
+        transaction.addSignature(publicKey, signature);
+      } else {
+        const legacyTransaction = transaction as Transaction;
+        const signature = await signMessage(
+          legacyTransaction.serializeMessage(),
+        );
+        // web3.js copies the signature into its own Buffer, so we don't use
+        // the global Buffer, which browsers don't have
+        legacyTransaction.addSignature(publicKey, signature as Buffer);
+      }
+      return transaction;
+    },
+  };
+};
+
+// Don't trust external signers to sign with the right key
+const verifySignature = (
+  signature: Uint8Array,
+  message: Uint8Array,
+  publicKey: PublicKey,
+) => {
+  if (!ed25519.verify(signature, message, publicKey.toBytes())) {
+    throw new Error(
+      `Signer returned an invalid signature for '${publicKey.toBase58()}'`,
+    );
+  }
+  return signature;
+};
+
+export const makeKeypairSigner = (keypair: Keypair): AsyncSigner => {
+  return makeAsyncSigner(keypair.publicKey, async (message) =>
+    ed25519.sign(message, keypair.secretKey.slice(0, 32)),
+  );
+};
+
+// Run a command that outputs a secret key (in any format parseSecretKey() reads)
+// each time we need to sign, eg 'pass show solana/deploy' or
+// 'op read op://Private/deploy/secret-key'. The secret key isn't kept between
+// signatures, and the bytes we decode are wiped after signing, but JavaScript
+// can't wipe the strings made while parsing the command's output.
+export const makeCommandSigner = async (
+  command: string,
+): Promise<AsyncSigner> => {
+  const { exec } = await import("child_process");
+  const { promisify } = await import("util");
+
+  // Just the 32 byte seed, for the caller to wipe when they're done with it
+  const getSeed = async (): Promise<Uint8Array> => {
+    let stdout: Buffer;
+    try {
+      ({ stdout } = await promisify(exec)(command, { encoding: "buffer" }));
+    } catch (thrownObject) {
+      // Not the error itself, as it includes stdout, which may have the
+      // secret key in it
+      const error = thrownObject as NodeJS.ErrnoException;
+      throw new Error(
+        `Could not get secret key from command '${command}' (exit code ${error.code})`,
+      );
+    }
+    try {
+      const { bytes } = decodeSecretKey(stdout.toString("utf8"));
+      const seed = bytes.slice(0, 32);
+      bytes.fill(0);
+      return seed;
+    } finally {
+      stdout.fill(0);
+    }
+  };
+
+  const initialSeed = await getSeed();
+  const publicKey = new PublicKey(ed25519.getPublicKey(initialSeed));
+  initialSeed.fill(0);
+  return makeAsyncSigner(publicKey, async (message) => {
+    const seed = await getSeed();
+    try {
+      return verifySignature(ed25519.sign(message, seed), message, publicKey);
+    } finally {
+      seed.fill(0);
+    }
+  });
+};
+
+export interface MakeHttpSignerOptions {
+  publicKey?: PublicKey;
+  headers?: Record<string, string>;
+}
+
+// Ask a remote signer to sign. See the README for the protocol.
+export const makeHttpSigner = async (
+  url: string,
+  options?: MakeHttpSignerOptions,
+): Promise<AsyncSigner> => {
+  const baseUrl = url.replace(/\/$/, "");
+  const headers = {
+    "Content-Type": "application/json",
+    ...options?.headers,
+  };
+
+  const request = async (path: string, body?: object) => {
+    const response = await fetch(`${baseUrl}${path}`, {
+      method: body ? "POST" : "GET",
+      headers,
+      body: body ? JSON.stringify(body) : undefined,
+    });
+    if (!response.ok) {
+      throw new Error(
+        `Remote signer at '${baseUrl}${path}' returned ${response.status} ${response.statusText}`,
+      );
+    }
+    return response.json();
+  };
+
+  let publicKey = options?.publicKey;
+  if (!publicKey) {
+    const response = await request("/public-key");
+    publicKey = new PublicKey(response.publicKey);
+  }
+  const signerPublicKey = publicKey;
+
+  return makeAsyncSigner(signerPublicKey, async (message) => {
+    const response = await request("/sign-message", {
+      publicKey: signerPublicKey.toBase58(),
+      message: encodeBase64(message),
+    });
+    return verifySignature(
+      decodeBase64(response.signature),
+      message,
+      signerPublicKey,
+    );
+  });
+};
+
+export interface InitializeSignerOptions extends InitializeKeypairOptions {
+  signer?: AsyncSigner;
+}
+
+// Like initializeKeypair(), but can also use a signer that isn't a keypair
+export const initializeSigner = async (
+  connection: Connection,
+  options?: InitializeSignerOptions,
+): Promise<AsyncSigner> => {
+  const {
+    signer,
+    airdropAmount = DEFAULT_AIRDROP_AMOUNT,
+    minimumBalance = DEFAULT_MINIMUM_BALANCE,
+  } = options || {};
+
+  if (!signer) {
+    return makeKeypairSigner(await initializeKeypair(connection, options));
+  }
+
+  if (airdropAmount) {
+    await fundIfRequired(
+      connection,
+      signer.publicKey,
+      airdropAmount,
+      minimumBalance,
+      options?.airdropOptions,
+    );
+  }
+  return signer;
+};
+
 // Send a versioned transaction with less boilerplate
 // https://www.quicknode.com/guides/solana-development/transactions/how-to-use-versioned-transactions-on-solana
-// TODO: maybe we should expose this? To discuss.
-const makeAndSendAndConfirmTransaction = async (
+export const makeAndSendAndConfirmTransaction = async (
   connection: Connection,
   instructions: Array<TransactionInstruction>,
-  signers: Array<Signer>,
-  payer: Keypair,
-) => {
+  signers: Array<Signer | AsyncSigner>,
+  payer: Signer | AsyncSigner,
+  commitment: Commitment = "finalized",
+): Promise<string> => {
   const latestBlockhash = (await connection.getLatestBlockhash("max"))
     .blockhash;
 
@@ -438,11 +3739,17 @@ const makeAndSendAndConfirmTransaction = async (
     instructions,
   }).compileToV0Message();
   const transaction = new VersionedTransaction(messageV0);
-  transaction.sign(signers);
+  transaction.sign(
+    signers.filter((signer): signer is Signer => !isAsyncSigner(signer)),
+  );
+  for (const signer of signers.filter(isAsyncSigner)) {
+    await signer.signTransaction(transaction);
+  }
 
   const signature = await connection.sendTransaction(transaction);
 
-  await confirmTransaction(connection, signature);
+  await confirmTransaction(connection, signature, commitment);
+  return signature;
 };
 
 // Create users, mints, create ATAs and mint tokens.
@@ -454,7 +3761,8 @@ export const createAccountsMintsAndTokenAccounts = async (
   usersAndTokenBalances: Array<Array<number>>,
   lamports: number,
   connection: Connection,
-  payer: Keypair,
+  payer: Signer | AsyncSigner,
+  options?: MakeKeypairsOptions,
 ) => {
   const userCount = usersAndTokenBalances.length;
   // Set the variable mintCount to the largest array in the usersAndTokenBalances array
@@ -462,8 +3770,15 @@ export const createAccountsMintsAndTokenAccounts = async (
     ...usersAndTokenBalances.map((mintBalances) => mintBalances.length),
   );
 
-  const users = makeKeypairs(userCount);
-  const mints = makeKeypairs(mintCount);
+  // Users and mints need different seeds, otherwise the first user
+  // would have the same keypair as the first mint.
+  const seed = options?.seed;
+  const users = makeKeypairs(userCount, {
+    seed: seed === undefined ? undefined : `${seed}:users`,
+  });
+  const mints = makeKeypairs(mintCount, {
+    seed: seed === undefined ? undefined : `${seed}:mints`,
+  });
 
   // This will be returned
   // [user index][mint index]address of token account